@@ -0,0 +1,260 @@
+use std::collections::VecDeque;
+use log::{trace, debug, info, warn, error}; // trace, debug, info, warn, error
+
+#[allow(dead_code)]
+fn _get_rid_of_log_unused_import_warnings() {
+    trace!("Example trace.");
+    debug!("Example debug.");
+    info!("Example info.");
+    warn!("Example warn.");
+    error!("Example error.");
+}
+
+/// Outcome of assigning every row of a candidate table to its own column
+#[derive(Clone, Debug, PartialEq)]
+pub enum Assignment {
+    /// Exactly one way to do it; entry i is the column given to row i
+    Unique(Vec<usize>),
+    /// More than one way; holds the assignments that were found
+    Multiple(Vec<Vec<usize>>),
+    /// No way to give every row a column of its own
+    Impossible,
+}
+
+/// Hopcroft-Karp over the rows and columns not marked as taken.
+/// Returns, for each row, the column it was matched to.
+fn hopcroft_karp(adj: &[Vec<usize>], n_cols: usize, row_taken: &[bool], col_taken: &[bool]) -> Vec<Option<usize>> {
+    const FREE: usize = usize::MAX;
+    let n_rows = adj.len();
+    let mut row_match: Vec<Option<usize>> = vec![None; n_rows];
+    let mut col_match: Vec<Option<usize>> = vec![None; n_cols];
+    let mut dist = vec![FREE; n_rows];
+
+    loop {
+        // Layer the free rows and everything reachable from them along
+        //  alternating paths.
+        let mut queue = VecDeque::new();
+        for r in 0..n_rows {
+            if !row_taken[r] && row_match[r].is_none() {
+                dist[r] = 0;
+                queue.push_back(r);
+            } else {
+                dist[r] = FREE;
+            }
+        }
+
+        let mut found = false;
+        while let Some(r) = queue.pop_front() {
+            for &c in adj[r].iter().filter(|&&c| !col_taken[c]) {
+                match col_match[c] {
+                    None => found = true,
+                    Some(r2) => {
+                        if dist[r2] == FREE {
+                            dist[r2] = dist[r] + 1;
+                            queue.push_back(r2);
+                        }
+                    }
+                }
+            }
+        }
+
+        if !found {
+            return row_match;
+        }
+
+        for r in 0..n_rows {
+            if !row_taken[r] && row_match[r].is_none() {
+                augment(r, adj, col_taken, &mut row_match, &mut col_match, &mut dist);
+            }
+        }
+    }
+}
+
+/// Look for a shortest augmenting path from row r along the BFS layers
+fn augment(r: usize, adj: &[Vec<usize>], col_taken: &[bool], row_match: &mut Vec<Option<usize>>,
+           col_match: &mut Vec<Option<usize>>, dist: &mut Vec<usize>) -> bool {
+    for &c in adj[r].iter().filter(|&&c| !col_taken[c]) {
+        let ok = match col_match[c] {
+            None => true,
+            Some(r2) => dist[r2] == dist[r] + 1 && augment(r2, adj, col_taken, row_match, col_match, dist),
+        };
+        if ok {
+            row_match[r] = Some(c);
+            col_match[c] = Some(r);
+            return true;
+        }
+    }
+    dist[r] = usize::MAX;
+    false
+}
+
+fn adjacency(candidates: &[Vec<bool>]) -> (Vec<Vec<usize>>, usize) {
+    let n_cols = candidates.iter().map(|row| row.len()).max().unwrap_or(0);
+    let adj = candidates.iter()
+        .map(|row| row.iter().enumerate().filter(|(_, &ok)| ok).map(|(c, _)| c).collect())
+        .collect();
+    (adj, n_cols)
+}
+
+/// A maximum matching of rows to columns, where row i may only take column
+///  j if candidates[i][j] is set.
+#[allow(dead_code)]
+pub fn maximum_matching(candidates: &[Vec<bool>]) -> Vec<Option<usize>> {
+    let (adj, n_cols) = adjacency(candidates);
+    hopcroft_karp(&adj, n_cols, &vec![false; adj.len()], &vec![false; n_cols])
+}
+
+/// Every way of giving each row its own column, up to `limit` of them.
+/// Backtracks on the row with the fewest columns left, and only follows a
+///  choice if the remaining rows can still all be matched, so no time is
+///  spent in dead ends.
+pub fn assignments(candidates: &[Vec<bool>], limit: Option<usize>) -> Vec<Vec<usize>> {
+    let (adj, n_cols) = adjacency(candidates);
+    let mut found = Vec::new();
+    let mut assigned: Vec<Option<usize>> = vec![None; adj.len()];
+    let mut col_taken = vec![false; n_cols];
+
+    if limit != Some(0) && is_completable(&adj, n_cols, &assigned, &col_taken) {
+        search(&adj, n_cols, &mut assigned, &mut col_taken, limit, &mut found);
+    }
+    found
+}
+
+fn is_completable(adj: &[Vec<usize>], n_cols: usize, assigned: &[Option<usize>], col_taken: &[bool]) -> bool {
+    let row_taken: Vec<bool> = assigned.iter().map(|x| x.is_some()).collect();
+    let matching = hopcroft_karp(adj, n_cols, &row_taken, col_taken);
+    (0..adj.len()).all(|r| row_taken[r] || matching[r].is_some())
+}
+
+fn search(adj: &[Vec<usize>], n_cols: usize, assigned: &mut Vec<Option<usize>>, col_taken: &mut Vec<bool>,
+          limit: Option<usize>, found: &mut Vec<Vec<usize>>) {
+    let next = (0..adj.len())
+        .filter(|&r| assigned[r].is_none())
+        .min_by_key(|&r| adj[r].iter().filter(|&&c| !col_taken[c]).count());
+
+    let r = match next {
+        Some(r) => r,
+        None => {
+            found.push(assigned.iter().map(|x| x.unwrap()).collect());
+            return;
+        }
+    };
+
+    for &c in &adj[r] {
+        if col_taken[c] {
+            continue;
+        }
+        assigned[r] = Some(c);
+        col_taken[c] = true;
+        if is_completable(adj, n_cols, assigned, col_taken) {
+            search(adj, n_cols, assigned, col_taken, limit, found);
+        }
+        assigned[r] = None;
+        col_taken[c] = false;
+
+        if Some(found.len()) == limit {
+            return;
+        }
+    }
+}
+
+/// Assign every row a column of its own and say whether that can be done
+///  in exactly one way, several ways, or not at all.
+pub fn solve(candidates: &[Vec<bool>]) -> Assignment {
+    let mut found = assignments(candidates, Some(2));
+    trace!("Assignments found: {:?}", found);
+    match found.len() {
+        0 => Assignment::Impossible,
+        1 => Assignment::Unique(found.remove(0)),
+        _ => Assignment::Multiple(found),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init() {
+        if env_logger::try_init().is_ok() {
+            info!("Initializing logging...");
+        }
+    }
+
+    fn table(rows: &[&[u8]]) -> Vec<Vec<bool>> {
+        rows.iter().map(|row| row.iter().map(|&x| x == 1).collect()).collect()
+    }
+
+    #[test]
+    fn test_maximum_matching() {
+        init();
+        let candidates = table(&[
+            &[1, 1, 0],
+            &[1, 0, 0],
+            &[1, 0, 0],
+        ]);
+        let matching = maximum_matching(&candidates);
+
+        assert_eq!(matching.iter().filter(|x| x.is_some()).count(), 2);
+        assert_eq!(matching[0], Some(1));
+    }
+
+    #[test]
+    fn test_solve_staircase() {
+        init();
+        let candidates = table(&[
+            &[0, 0, 1, 1],
+            &[1, 1, 1, 1],
+            &[0, 1, 1, 1],
+            &[0, 0, 0, 1],
+        ]);
+
+        assert_eq!(solve(&candidates), Assignment::Unique(vec![2, 0, 1, 3]));
+    }
+
+    #[test]
+    fn test_solve_not_a_staircase() {
+        init();
+        // No row has a single candidate to start eliminating from, but
+        //  there is still only one answer.
+        let candidates = table(&[
+            &[1, 1, 0],
+            &[0, 1, 1],
+            &[1, 0, 0],
+        ]);
+
+        assert_eq!(solve(&candidates), Assignment::Unique(vec![1, 2, 0]));
+
+        let ambiguous = table(&[
+            &[1, 1, 0],
+            &[1, 1, 0],
+            &[0, 0, 1],
+        ]);
+
+        assert_eq!(solve(&ambiguous), Assignment::Multiple(vec![vec![0, 1, 2], vec![1, 0, 2]]));
+
+        let impossible = table(&[
+            &[1, 0, 0],
+            &[1, 0, 0],
+            &[0, 1, 1],
+        ]);
+
+        assert_eq!(solve(&impossible), Assignment::Impossible);
+    }
+
+    #[test]
+    fn test_assignments_enumeration() {
+        init();
+        let everything = vec![vec![true; 4]; 4];
+
+        assert_eq!(assignments(&everything, None).len(), 24);
+        assert_eq!(assignments(&everything, Some(5)).len(), 5);
+        assert_eq!(assignments(&everything, Some(0)).len(), 0);
+
+        // More columns than rows leaves some columns unused
+        let wide = table(&[
+            &[1, 0, 1],
+            &[1, 0, 0],
+        ]);
+        assert_eq!(assignments(&wide, None), vec![vec![2, 0]]);
+    }
+}
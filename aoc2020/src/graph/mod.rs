@@ -0,0 +1,401 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+use log::{trace, debug, info, warn, error}; // trace, debug, info, warn, error
+use crate::matrix::SquareMatrix;
+
+#[allow(dead_code)]
+fn _get_rid_of_log_unused_import_warnings() {
+    trace!("Example trace.");
+    debug!("Example debug.");
+    info!("Example info.");
+    warn!("Example warn.");
+    error!("Example error.");
+}
+
+/// Descendant counts, or why there aren't any
+type Counts = Result<Rc<Vec<u64>>, &'static str>;
+
+/// LabelledDigraph
+/// Directed, weighted graph read off a labelled adjacency matrix: a non-zero
+///  entry (i, j) is an edge from label i to label j with that weight.
+/// Adjacency lists are built once up front so that the traversals below are
+///  linear in the number of edges instead of scanning whole matrix rows.
+/// The edges can't change once built, so cached answers never go stale.
+pub struct LabelledDigraph {
+    labels: Vec<String>,
+    index: HashMap<String, usize>,
+    successors: Vec<Vec<(usize, u32)>>,
+    predecessors: Vec<Vec<(usize, u32)>>,
+    reach_cache: RefCell<HashMap<usize, Rc<Vec<bool>>>>,
+    reaching_cache: RefCell<HashMap<usize, Rc<Vec<bool>>>>,
+    counts_cache: RefCell<Option<Counts>>,
+}
+
+impl LabelledDigraph {
+    pub fn from_matrix(m: &SquareMatrix<u32>) -> LabelledDigraph {
+        let n = m.size();
+        let mut successors = vec![Vec::new(); n];
+        let mut predecessors = vec![Vec::new(); n];
+
+        for (i, j, w) in m.entries() {
+            successors[i].push((j, w));
+            predecessors[j].push((i, w));
+        }
+
+        let labels = m.get_labels().to_vec();
+        let mut index = HashMap::new();
+        for (i, label) in labels.iter().enumerate() {
+            index.entry(label.clone()).or_insert(i);
+        }
+
+        LabelledDigraph {
+            labels,
+            index,
+            successors,
+            predecessors,
+            reach_cache: RefCell::new(HashMap::new()),
+            reaching_cache: RefCell::new(HashMap::new()),
+            counts_cache: RefCell::new(None),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn index_of(&self, label: &str) -> Option<usize> {
+        self.index.get(label).copied()
+    }
+
+    #[allow(dead_code)]
+    pub fn get_label(&self, n: usize) -> &str {
+        &self.labels[n]
+    }
+
+    /// Outgoing edges of a node as (target, weight)
+    #[allow(dead_code)]
+    pub fn successors(&self, n: usize) -> &[(usize, u32)] {
+        &self.successors[n]
+    }
+
+    /// Incoming edges of a node as (source, weight)
+    #[allow(dead_code)]
+    pub fn predecessors(&self, n: usize) -> &[(usize, u32)] {
+        &self.predecessors[n]
+    }
+
+    /// Breadth-first search along the given edge lists.  The start node is
+    ///  only marked if it can be reached again through some cycle.
+    fn search(edges: &[Vec<(usize, u32)>], start: usize) -> Vec<bool> {
+        let mut seen = vec![false; edges.len()];
+        let mut queue: VecDeque<usize> = edges[start].iter().map(|e| e.0).collect();
+
+        while let Some(n) = queue.pop_front() {
+            if seen[n] {
+                continue;
+            }
+            seen[n] = true;
+            queue.extend(edges[n].iter().map(|e| e.0).filter(|&x| !seen[x]));
+        }
+
+        seen
+    }
+
+    /// Search from n, or the answer to it kept from last time.  Handing
+    ///  out an Rc keeps a cached lookup from copying the whole result.
+    fn cached_search(cache: &RefCell<HashMap<usize, Rc<Vec<bool>>>>, edges: &[Vec<(usize, u32)>], n: usize) -> Rc<Vec<bool>> {
+        cache.borrow_mut()
+            .entry(n)
+            .or_insert_with(|| Rc::new(LabelledDigraph::search(edges, n)))
+            .clone()
+    }
+
+    /// Every node reachable from n by one or more edges.  Results are cached
+    ///  per start node, so asking "does x reach y" for many pairs only walks
+    ///  the graph once per x.
+    pub fn reachable_from(&self, n: usize) -> Rc<Vec<bool>> {
+        LabelledDigraph::cached_search(&self.reach_cache, &self.successors, n)
+    }
+
+    /// Every node that can reach n by one or more edges.  Cached per end
+    ///  node, as reachable_from is per start node.
+    pub fn reaching(&self, n: usize) -> Rc<Vec<bool>> {
+        LabelledDigraph::cached_search(&self.reaching_cache, &self.predecessors, n)
+    }
+
+    /// Does there exist a path of at least one edge from `from` to `to`?
+    #[allow(dead_code)]
+    pub fn reaches(&self, from: usize, to: usize) -> bool {
+        self.reachable_from(from)[to]
+    }
+
+    /// Labels of every node that can reach the given one, in label order
+    #[allow(dead_code)]
+    pub fn ancestors_of(&self, label: &str) -> Option<Vec<String>> {
+        let n = self.index_of(label)?;
+        Some(self.reaching(n).iter()
+            .enumerate()
+            .filter(|(_, &x)| x)
+            .map(|(i, _)| self.labels[i].clone())
+            .collect())
+    }
+
+    /// Labels of every node reachable from the given one, in label order
+    #[allow(dead_code)]
+    pub fn descendants_of(&self, label: &str) -> Option<Vec<String>> {
+        let n = self.index_of(label)?;
+        Some(self.reachable_from(n).iter()
+            .enumerate()
+            .filter(|(_, &x)| x)
+            .map(|(i, _)| self.labels[i].clone())
+            .collect())
+    }
+
+    /// Kahn's algorithm.  On failure the error holds a cycle, as from
+    ///  find_cycle.
+    pub fn topological_sort(&self) -> Result<Vec<usize>, Vec<usize>> {
+        let mut in_degree: Vec<usize> = self.predecessors.iter().map(|p| p.len()).collect();
+        let mut queue: VecDeque<usize> = (0..self.size()).filter(|&i| in_degree[i] == 0).collect();
+        let mut order = Vec::with_capacity(self.size());
+
+        while let Some(n) = queue.pop_front() {
+            order.push(n);
+            for &(m, _) in &self.successors[n] {
+                in_degree[m] -= 1;
+                if in_degree[m] == 0 {
+                    queue.push_back(m);
+                }
+            }
+        }
+
+        if order.len() == self.size() {
+            Ok(order)
+        } else {
+            Err(self.find_cycle().unwrap_or_default())
+        }
+    }
+
+    /// Find some cycle, returned as the list of nodes along it
+    pub fn find_cycle(&self) -> Option<Vec<usize>> {
+        // 0 = unvisited, 1 = on the current path, 2 = finished
+        let mut state = vec![0u8; self.size()];
+        let mut parent = vec![usize::MAX; self.size()];
+
+        for root in 0..self.size() {
+            if state[root] != 0 {
+                continue;
+            }
+            // Explicit stack of (node, next edge to look at)
+            let mut stack = vec![(root, 0)];
+            state[root] = 1;
+
+            while let Some(&mut (n, ref mut edge)) = stack.last_mut() {
+                if *edge < self.successors[n].len() {
+                    let m = self.successors[n][*edge].0;
+                    *edge += 1;
+                    match state[m] {
+                        0 => {
+                            state[m] = 1;
+                            parent[m] = n;
+                            stack.push((m, 0));
+                        },
+                        1 => {
+                            let mut cycle = vec![n];
+                            let mut cur = n;
+                            while cur != m {
+                                cur = parent[cur];
+                                cycle.push(cur);
+                            }
+                            cycle.reverse();
+                            trace!("Found cycle: {:?}", cycle);
+                            return Some(cycle);
+                        },
+                        _ => {}
+                    }
+                } else {
+                    state[n] = 2;
+                    stack.pop();
+                }
+            }
+        }
+
+        None
+    }
+
+    #[allow(dead_code)]
+    pub fn has_cycle(&self) -> bool {
+        self.find_cycle().is_some()
+    }
+
+    /// Weighted descendant count of every node: the sum over each outgoing
+    ///  edge of weight * (1 + count of the target).  For the bag rules this
+    ///  is how many bags a bag holds.  Each node is computed once, in reverse
+    ///  topological order, and the whole table is kept for later calls.
+    pub fn descendant_counts(&self) -> Counts {
+        self.counts_cache.borrow_mut()
+            .get_or_insert_with(|| self.count_descendants().map(Rc::new))
+            .clone()
+    }
+
+    fn count_descendants(&self) -> Result<Vec<u64>, &'static str> {
+        let order = self.topological_sort().map_err(|_| "Graph has a cycle.")?;
+        let mut counts = vec![0u64; self.size()];
+
+        for &n in order.iter().rev() {
+            counts[n] = self.successors[n].iter()
+                .map(|&(m, w)| w as u64 * (1 + counts[m]))
+                .sum();
+        }
+
+        Ok(counts)
+    }
+
+    pub fn weighted_descendant_count(&self, label: &str) -> Result<u64, &'static str> {
+        let n = self.index_of(label).ok_or("No such label.")?;
+        Ok(self.descendant_counts()?[n])
+    }
+
+    /// Every simple path from one node to another
+    #[allow(dead_code)]
+    pub fn all_paths(&self, from: usize, to: usize) -> Vec<Vec<usize>> {
+        let mut paths = Vec::new();
+        let mut on_path = vec![false; self.size()];
+        let mut path = vec![from];
+        on_path[from] = true;
+        self.extend_paths(to, &mut path, &mut on_path, &mut paths);
+        paths
+    }
+
+    fn extend_paths(&self, to: usize, path: &mut Vec<usize>, on_path: &mut Vec<bool>, paths: &mut Vec<Vec<usize>>) {
+        let n = *path.last().unwrap();
+        for &(m, _) in &self.successors[n] {
+            if m == to {
+                let mut found = path.clone();
+                found.push(m);
+                paths.push(found);
+            } else if !on_path[m] {
+                on_path[m] = true;
+                path.push(m);
+                self.extend_paths(to, path, on_path, paths);
+                path.pop();
+                on_path[m] = false;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init() {
+        if env_logger::try_init().is_ok() {
+            info!("Initializing logging...");
+        }
+    }
+
+    fn make_graph(edges: &[(&str, &str, u32)]) -> LabelledDigraph {
+        let mut m = SquareMatrix::new(0);
+        for (from, to, w) in edges {
+            m.set_in_labelled_row(from.to_string(), to.to_string(), *w);
+        }
+        LabelledDigraph::from_matrix(&m)
+    }
+
+    /// The bag rules from the day 7 example
+    fn bag_graph() -> LabelledDigraph {
+        make_graph(&[
+            ("light red", "bright white", 1),
+            ("light red", "muted yellow", 2),
+            ("dark orange", "bright white", 3),
+            ("dark orange", "muted yellow", 4),
+            ("bright white", "shiny gold", 1),
+            ("muted yellow", "shiny gold", 2),
+            ("muted yellow", "faded blue", 9),
+            ("shiny gold", "dark olive", 1),
+            ("shiny gold", "vibrant plum", 2),
+            ("dark olive", "faded blue", 3),
+            ("dark olive", "dotted black", 4),
+            ("vibrant plum", "faded blue", 5),
+            ("vibrant plum", "dotted black", 6),
+        ])
+    }
+
+    #[test]
+    fn test_reachability() {
+        init();
+        let g = bag_graph();
+        let light_red = g.index_of("light red").unwrap();
+        let shiny_gold = g.index_of("shiny gold").unwrap();
+
+        assert!(g.reaches(light_red, shiny_gold));
+        assert!(!g.reaches(shiny_gold, light_red));
+        assert!(!g.reaches(shiny_gold, shiny_gold));
+
+        assert_eq!(g.ancestors_of("shiny gold").unwrap(), vec![
+            "light red".to_string(), "bright white".to_string(),
+            "muted yellow".to_string(), "dark orange".to_string(),
+        ]);
+        assert_eq!(g.descendants_of("dark olive").unwrap(), vec![
+            "faded blue".to_string(), "dotted black".to_string(),
+        ]);
+        assert_eq!(g.ancestors_of("plaid"), None);
+
+        // Cached answers are the same as fresh ones
+        assert_eq!(g.reaching(shiny_gold), g.reaching(shiny_gold));
+        assert_eq!(g.reaching_cache.borrow().len(), 1);
+        assert!(Rc::ptr_eq(&g.reachable_from(light_red), &g.reachable_from(light_red)));
+    }
+
+    #[test]
+    fn test_descendant_counts() {
+        init();
+        let g = bag_graph();
+
+        assert_eq!(g.weighted_descendant_count("shiny gold"), Ok(32));
+        assert_eq!(g.weighted_descendant_count("faded blue"), Ok(0));
+        assert!(g.weighted_descendant_count("plaid").is_err());
+
+        // Worked out once and shared after that
+        assert!(Rc::ptr_eq(&g.descendant_counts().unwrap(), &g.descendant_counts().unwrap()));
+    }
+
+    #[test]
+    fn test_topological_sort_and_cycles() {
+        init();
+        let g = bag_graph();
+        let order = g.topological_sort().unwrap();
+
+        assert_eq!(order.len(), g.size());
+        for &n in &order {
+            let pos = order.iter().position(|&x| x == n).unwrap();
+            for &(m, _) in g.successors(n) {
+                assert!(order.iter().position(|&x| x == m).unwrap() > pos);
+            }
+        }
+        assert!(!g.has_cycle());
+
+        let cyclic = make_graph(&[("a", "b", 1), ("b", "c", 1), ("c", "a", 1), ("c", "d", 1)]);
+        let cycle = cyclic.find_cycle().unwrap();
+        let names: Vec<&str> = cycle.iter().map(|&n| cyclic.get_label(n)).collect();
+        assert_eq!(names, vec!["a", "b", "c"]);
+        assert_eq!(cyclic.topological_sort(), Err(cycle));
+        assert!(cyclic.descendant_counts().is_err());
+    }
+
+    #[test]
+    fn test_all_paths() {
+        init();
+        let g = bag_graph();
+        let light_red = g.index_of("light red").unwrap();
+        let shiny_gold = g.index_of("shiny gold").unwrap();
+        let faded_blue = g.index_of("faded blue").unwrap();
+
+        assert_eq!(g.all_paths(light_red, shiny_gold).len(), 2);
+        // light red -> {bright white, muted yellow} -> shiny gold -> {olive, plum} -> faded blue,
+        //  plus light red -> muted yellow -> faded blue
+        assert_eq!(g.all_paths(light_red, faded_blue).len(), 5);
+        assert_eq!(g.all_paths(shiny_gold, light_red).len(), 0);
+    }
+}
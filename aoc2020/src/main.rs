@@ -2,12 +2,13 @@ mod problems;
 mod util;
 mod matrix;
 mod virtualmachine;
+mod graph;
+mod assignment;
 
 use crate::util::load_file;
 
 // use std::env;
-use log::{info, warn, Level}; // trace, debug, info, warn, error
-use env_logger;
+use log::{info, warn, error, Level}; // trace, debug, info, warn, error
 use std::io::Write;
 use std::time::{Instant, Duration};
 use rustop::opts;
@@ -84,6 +85,172 @@ fn run_problem(num: i32, input: Vec<String>) {
     }
 }
 
+/// Parsed subcommand options, or exit with rustop's message
+fn parsed_or_exit<T>(parsed: Result<T, rustop::Error>) -> T {
+    match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run_vm_debug(args: &[String]) {
+    let vm_opts = opts! {
+        command_name "main vm-debug";
+        synopsis "Step through a boot code program.";
+        opt boot:bool, desc:"Stop continue at the first repeated instruction, as in day 8.";
+        param program:Option<String>, desc:"Boot code to load (default: day 8 input).";
+    };
+    let (vm_args, _rest) = parsed_or_exit(vm_opts.parse_args(args.iter().map(|x| x.as_str())));
+    let filename = vm_args.program.unwrap_or_else(|| "aoc2020/inputs/08.txt".to_string());
+    if let Err(e) = virtualmachine::run_debugger(load_file(filename), vm_args.boot) {
+        error!("{}", e);
+    }
+}
+
+fn run_ksum(args: &[String]) {
+    let ksum_opts = opts! {
+        command_name "main ksum";
+        synopsis "Find expense report entries that add up to a target.";
+        opt target:u64=problems::problem01::EXPENSE_TARGET, desc:"Sum to look for.";
+        opt k:usize=2, desc:"Number of entries to add up.";
+        opt all:bool, desc:"List every solution, not just the first.";
+        param report:Option<String>, desc:"Expense report to search (default: day 1 input).";
+    };
+    let (ksum_args, _rest) = parsed_or_exit(ksum_opts.parse_args(args.iter().map(|x| x.as_str())));
+    let filename = ksum_args.report.unwrap_or_else(|| "aoc2020/inputs/01.txt".to_string());
+    let values = match problems::problem01::parse_report(&load_file(filename)) {
+        Ok(values) => values,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let solutions = problems::problem01::k_sum(&values, ksum_args.k, ksum_args.target, ksum_args.all);
+    if solutions.is_empty() {
+        warn!("No {} entries add up to {}", ksum_args.k, ksum_args.target);
+    }
+    for solution in solutions {
+        info!("Entries {:?} at lines {:?}; product {}", solution.entries, solution.indices, solution.product());
+    }
+}
+
+fn run_passwords(args: &[String]) {
+    let pw_opts = opts! {
+        command_name "main passwords";
+        synopsis "List the passwords that break a policy, and why.";
+        opt policy:String=String::from("sled"), desc:"Base policy: sled, toboggan or none.";
+        opt min_length:Option<usize>, desc:"Also require at least this many characters.";
+        opt regex:Option<String>, desc:"Also require the password to match this regex.";
+        opt classes:Option<String>, desc:"Also require these character classes (lower,upper,digit,symbol).";
+        param database:Option<String>, desc:"Password database (default: day 2 input).";
+    };
+    let (pw_args, _rest) = parsed_or_exit(pw_opts.parse_args(args.iter().map(|x| x.as_str())));
+    let policy = match problems::problem02::build_policy(&pw_args.policy, pw_args.min_length,
+                                                         pw_args.regex.as_deref(), pw_args.classes.as_deref()) {
+        Ok(policy) => policy,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let filename = pw_args.database.unwrap_or_else(|| "aoc2020/inputs/02.txt".to_string());
+    let input = load_file(filename);
+    let report = problems::problem02::violation_report(&input, &policy);
+    for line in &report {
+        info!("{}", line);
+    }
+    info!("{} of {} passwords rejected", report.len(), input.len());
+}
+
+fn run_slopes(args: &[String]) {
+    let slope_opts = opts! {
+        command_name "main slopes";
+        synopsis "Count the trees hit on each slope, search for the best one, and draw the paths.";
+        opt slopes:Option<String>, desc:"Slopes as right,down separated by spaces (default: the part 2 slopes).";
+        opt search:Option<String>, desc:"Search every slope up to max_right,max_down for the fewest trees.";
+        opt draw:bool, desc:"Print the map with the paths on it.";
+        opt image:Option<String>, desc:"Save the map with the paths on it as a PPM image.";
+        param map:Option<String>, desc:"Map to sled down (default: day 3 input).";
+    };
+    let (slope_args, _rest) = parsed_or_exit(slope_opts.parse_args(args.iter().map(|x| x.as_str())));
+    let filename = slope_args.map.unwrap_or_else(|| "aoc2020/inputs/03.txt".to_string());
+    let landscape = problems::problem03::ArboralLandscape::new(load_file(filename));
+
+    let slopes: Result<Vec<problems::problem03::Slope>, String> = match &slope_args.slopes {
+        Some(list) => list.split_whitespace().map(|x| x.parse()).collect(),
+        None => Ok(problems::problem03::PART_2_SLOPES.to_vec()),
+    };
+    let slopes = match slopes {
+        Ok(slopes) => slopes,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let paths: Vec<problems::problem03::Path> = slopes.iter().map(|&s| landscape.trace(s)).collect();
+    for path in &paths {
+        info!("Right {}, down {}: {} trees", path.slope.right, path.slope.down, path.trees());
+    }
+    info!("Product: {}", paths.iter().map(|p| p.trees()).product::<u128>());
+
+    if let Some(limit) = slope_args.search {
+        match limit.parse::<problems::problem03::Slope>() {
+            Ok(max) => if let Some((fewest, best)) = landscape.fewest_trees(0..=max.right, 1..=max.down) {
+                let names: Vec<String> = best.iter().map(|s| format!("{},{}", s.right, s.down)).collect();
+                info!("Fewest trees: {} on {}", fewest, names.join(" "));
+            },
+            Err(e) => error!("{}", e),
+        }
+    }
+
+    let repeats = landscape.repeats_needed(&paths);
+    if slope_args.draw {
+        print!("{}", landscape.render(repeats, &paths));
+    }
+    if let Some(image) = slope_args.image {
+        if let Err(e) = landscape.write_image(&image, repeats, &paths, 4) {
+            error!("Can't write {}: {}", image, e);
+        }
+    }
+}
+
+fn run_passports(args: &[String]) {
+    let pp_opts = opts! {
+        command_name "main passports";
+        synopsis "List every passport that fails validation, and why.";
+        opt schema:Option<String>, desc:"Schema file to validate against (default: the puzzle's rules).";
+        opt fields_only:bool, desc:"Only check that required fields are there.";
+        param batch:Option<String>, desc:"Passport batch file (default: day 4 input).";
+    };
+    let (pp_args, _rest) = parsed_or_exit(pp_opts.parse_args(args.iter().map(|x| x.as_str())));
+    let schema = match &pp_args.schema {
+        Some(filename) => problems::problem04::Schema::load(filename),
+        None => Ok(problems::problem04::Schema::standard()),
+    };
+    let schema = match schema {
+        Ok(schema) => schema,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let filename = pp_args.batch.unwrap_or_else(|| "aoc2020/inputs/04.txt".to_string());
+    let passports = problems::problem04::semi_questionable_passport_factory(load_file(filename));
+    let mut rejected = 0;
+    for (i, passport) in passports.iter().enumerate() {
+        let failures = schema.check(passport, !pp_args.fields_only);
+        if !failures.is_empty() {
+            rejected += 1;
+            let problems: Vec<String> = failures.iter().map(|x| x.to_string()).collect();
+            info!("passport {} (line {}): {}", i + 1, passport.line, problems.join("; "));
+        }
+    }
+    info!("{} of {} passports rejected", rejected, passports.len());
+}
+
 fn main() {
     // Set up logging
     env_logger::builder()
@@ -103,6 +270,18 @@ fn main() {
         })
         .init();
 
+    // Subcommands come before the usual options
+    let raw_args: Vec<String> = std::env::args().collect();
+    let rest = raw_args.get(2..).unwrap_or(&[]);
+    match raw_args.get(1).map(|x| x.as_str()) {
+        Some("vm-debug") => return run_vm_debug(rest),
+        Some("ksum") => return run_ksum(rest),
+        Some("passwords") => return run_passwords(rest),
+        Some("slopes") => return run_slopes(rest),
+        Some("passports") => return run_passports(rest),
+        _ => {}
+    }
+
     let opts = opts! {
         synopsis "Advent of Code 2020";
         opt run_all:bool, desc: "Run all problems.";
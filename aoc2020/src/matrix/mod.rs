@@ -1,5 +1,10 @@
-#[allow(dead_code)]
-
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use petgraph::graph::{Graph, NodeIndex};
+use petgraph::graphmap::DiGraphMap;
+use petgraph::visit::EdgeRef;
 use log::{trace, debug, info, warn, error}; // trace, debug, info, warn, error
 
 #[allow(dead_code)]
@@ -11,109 +16,614 @@ fn _get_rid_of_log_unused_import_warnings() {
     error!("Example error.");
 }
 
+/// Error for sums and products that don't fit in a u32 entry
+const OVERFLOW: &str = "Matrix entry overflowed.";
+
+/// How a SquareMatrix keeps its values.  Dense is a plain grid; sparse only
+///  keeps the non-zero entries of each row, which suits adjacency matrices
+///  that are mostly empty and grow one node at a time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Storage {
+    Dense,
+    Sparse,
+}
+
+/// Rows of a sparse matrix are ordered maps so that iterating over the
+///  non-zero entries gives the same order as the dense layout.
+#[derive(Clone, Debug)]
+enum Values<T> {
+    Dense(Vec<Vec<T>>),
+    Sparse(Vec<BTreeMap<usize, T>>),
+}
+
+#[derive(Clone, Debug)]
 pub struct SquareMatrix<T> {
     dim: usize,
-    values: Vec<Vec<T>>,
-    labels: Vec<String>
+    values: Values<T>,
+    labels: Vec<String>,
+    index: HashMap<String, usize>,
+}
+
+/// Matrices are equal if they hold the same values under the same labels,
+///  however they happen to be stored.
+impl PartialEq for SquareMatrix<u32> {
+    fn eq(&self, other: &SquareMatrix<u32>) -> bool {
+        self.dim == other.dim && self.labels == other.labels && self.entries().eq(other.entries())
+    }
 }
 
 /// Implement other impl for other types...
 impl SquareMatrix<u32> {
     pub fn new(n: usize) -> SquareMatrix<u32> {
-        SquareMatrix {
+        SquareMatrix::with_storage(n, Storage::Dense)
+    }
+
+    pub fn with_storage(n: usize, storage: Storage) -> SquareMatrix<u32> {
+        let values = match storage {
+            Storage::Dense => Values::Dense(vec![vec![0; n]; n]),
+            Storage::Sparse => Values::Sparse(vec![BTreeMap::new(); n]),
+        };
+        let mut m = SquareMatrix {
             dim: n,
-            values: vec![vec![0; n]; n],
-            labels: vec!["".to_string(); n]
+            values,
+            labels: vec!["".to_string(); n],
+            index: HashMap::new(),
+        };
+        m.reindex();
+        m
+    }
+
+    #[allow(dead_code)]
+    pub fn storage(&self) -> Storage {
+        match self.values {
+            Values::Dense(_) => Storage::Dense,
+            Values::Sparse(_) => Storage::Sparse,
         }
     }
 
+    /// Rebuild the label index from scratch.  Blank labels are unlabelled
+    ///  rows and are not indexed.
+    fn reindex(&mut self) {
+        self.index.clear();
+        for (i, label) in self.labels.iter().enumerate() {
+            if !label.is_empty() {
+                self.index.entry(label.clone()).or_insert(i);
+            }
+        }
+    }
+
+    /// Growing a sparse matrix only adds empty rows, so adding nodes one at
+    ///  a time stays cheap no matter how big it gets.
     pub fn resize(&mut self, n: usize) {
+        let shrinking = n < self.dim;
         self.dim = n;
         
-        self.values.resize(n, vec![0; n]);
-        for row in 0..n {
-            self.values[row].resize(n, 0);
+        match &mut self.values {
+            Values::Dense(rows) => {
+                rows.resize(n, vec![0; n]);
+                for row in rows.iter_mut() {
+                    row.resize(n, 0);
+                }
+            },
+            Values::Sparse(rows) => {
+                rows.resize(n, BTreeMap::new());
+                if shrinking {
+                    for row in rows.iter_mut() {
+                        row.split_off(&n);
+                    }
+                }
+            },
         }
         self.labels.resize(n, "".to_string());
+
+        if shrinking {
+            self.reindex();
+        }
     }
 
     pub fn add_labelled_row(&mut self, row_name: String) {
         self.resize(self.dim+1);
+        if !row_name.is_empty() {
+            self.index.entry(row_name.clone()).or_insert(self.dim-1);
+        }
         self.labels[self.dim-1] = row_name;
     }
 
-    pub fn get_labelled_row(&mut self, row_name: String) -> Vec<u32> {
-        let index = self.labels.iter().position(|r| r.eq(&row_name));
+    pub fn get(&self, row: usize, col: usize) -> u32 {
+        match &self.values {
+            Values::Dense(rows) => rows[row][col],
+            Values::Sparse(rows) => {
+                assert!(col < self.dim, "column {} out of range for dimension {}", col, self.dim);
+                rows[row].get(&col).copied().unwrap_or(0)
+            },
+        }
+    }
 
-        match index {
-            Some(x) => {
-                return self.values[x].clone();
-            }
-            None => {
-                return Vec::new();
-            }
+    pub fn set(&mut self, row: usize, col: usize, value: u32) {
+        match &mut self.values {
+            Values::Dense(rows) => rows[row][col] = value,
+            Values::Sparse(rows) => {
+                assert!(col < self.dim, "column {} out of range for dimension {}", col, self.dim);
+                if value == 0 {
+                    rows[row].remove(&col);
+                } else {
+                    rows[row].insert(col, value);
+                }
+            },
+        }
+    }
+
+    fn add_to(&mut self, row: usize, col: usize, value: u32) -> Result<(), &'static str> {
+        let v = self.get(row, col).checked_add(value).ok_or(OVERFLOW)?;
+        self.set(row, col, v);
+        Ok(())
+    }
+
+    /// Non-zero entries of a row as (column, value), in column order
+    pub fn row_entries(&self, row: usize) -> Box<dyn Iterator<Item = (usize, u32)> + '_> {
+        match &self.values {
+            Values::Dense(rows) => Box::new(
+                rows[row].iter().copied().enumerate().filter(|&(_, v)| v != 0)
+            ),
+            Values::Sparse(rows) => Box::new(rows[row].iter().map(|(&j, &v)| (j, v))),
+        }
+    }
+
+    /// Every non-zero entry as (row, column, value), in row-major order
+    pub fn entries(&self) -> impl Iterator<Item = (usize, usize, u32)> + '_ {
+        (0..self.dim).flat_map(move |i| self.row_entries(i).map(move |(j, v)| (i, j, v)))
+    }
+
+    #[allow(dead_code)]
+    pub fn nonzero_count(&self) -> usize {
+        match &self.values {
+            Values::Dense(_) => self.entries().count(),
+            Values::Sparse(rows) => rows.iter().map(|row| row.len()).sum(),
+        }
+    }
+
+    /// Get the row for a label, or None if no such label exists
+    #[allow(dead_code)]
+    pub fn get_labelled_row(&self, row_name: &str) -> Option<Cow<'_, [u32]>> {
+        self.index_of(row_name).map(|x| self.get_row(x))
+    }
+
+    /// Get a row by index.  Dense rows are borrowed; sparse ones have to be
+    ///  filled out, so prefer row_entries for those.
+    #[allow(dead_code)]
+    pub fn get_row(&self, row: usize) -> Cow<'_, [u32]> {
+        match &self.values {
+            Values::Dense(rows) => Cow::Borrowed(&rows[row][..]),
+            Values::Sparse(_) => {
+                let mut full = vec![0; self.dim];
+                for (j, v) in self.row_entries(row) {
+                    full[j] = v;
+                }
+                Cow::Owned(full)
+            },
+        }
+    }
+
+    /// Get a single value by row and column label
+    #[allow(dead_code)]
+    pub fn get_labelled(&self, row_name: &str, col_name: &str) -> Option<u32> {
+        match (self.index_of(row_name), self.index_of(col_name)) {
+            (Some(x), Some(y)) => Some(self.get(x, y)),
+            _ => None,
         }
     }
 
     #[allow(dead_code)]
     pub fn set_in_labelled_row_usize(&mut self, row_name: String, col: usize, value: u32) {
-        let index = self.labels.iter().position(|r| r == &row_name);
-        match index {
-            Some(x) => self.values[x][col] = value,
+        match self.index_of(&row_name) {
+            Some(x) => self.set(x, col, value),
             None => {
                 self.add_labelled_row(row_name);
-                self.values[self.dim-1][col] = value;
+                self.set(self.dim-1, col, value);
             },
         }
     }
 
     pub fn set_in_labelled_row(&mut self, row_name: String, col_name: String, value: u32) {
-        let index_row = self.labels.iter().position(|r| r == &row_name);
-        
-        match index_row {
-            Some(x) => {
-                let index_col = self.labels.iter().position(|r| r == &col_name);
-                match index_col {
-                    Some(y) => self.values[x][y] = value,
-                    None => {
-                        self.add_labelled_row(col_name);
-                        self.values[x][self.dim-1] = value;
-                    }
-                }
-            }
+        let row = match self.index_of(&row_name) {
+            Some(x) => x,
             None => {
                 self.add_labelled_row(row_name);
-                let index_col = self.labels.iter().position(|r| r == &col_name);
-                match index_col {
-                    Some(y) => self.values[self.dim-1][y] = value,
-                    None => {
-                        self.add_labelled_row(col_name);
-                        self.values[self.dim-2][self.dim-1] = value;
-                    }
+                self.dim-1
+            }
+        };
+        let col = match self.index_of(&col_name) {
+            Some(y) => y,
+            None => {
+                self.add_labelled_row(col_name);
+                self.dim-1
+            }
+        };
+        self.set(row, col, value);
+    }
+
+    #[allow(dead_code)]
+    pub fn size(&self) -> usize {
+        self.dim
+    }
+
+    pub fn index_of(&self, n: &str) -> Option<usize> {
+        self.index.get(n).copied()
+    }
+
+    #[allow(dead_code)]
+    pub fn get_label(&self, n: usize) -> &str {
+        &self.labels[n]
+    }
+
+    /// Every row, filled out.  Only cheap for dense matrices.
+    #[allow(dead_code)]
+    pub fn get_values(&self) -> Cow<'_, [Vec<u32>]> {
+        match &self.values {
+            Values::Dense(rows) => Cow::Borrowed(&rows[..]),
+            Values::Sparse(_) => Cow::Owned((0..self.dim).map(|i| self.get_row(i).into_owned()).collect()),
+        }
+    }
+
+    pub fn get_labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    /// Identity matrix carrying the given labels
+    #[allow(dead_code)]
+    pub fn identity(labels: &[String]) -> SquareMatrix<u32> {
+        let mut m = SquareMatrix::new(labels.len());
+        for (i, label) in labels.iter().enumerate() {
+            m.set(i, i, 1);
+            m.labels[i] = label.clone();
+        }
+        m.reindex();
+        m
+    }
+
+    /// Empty matrix with the same labels as this one
+    fn zeroed(&self) -> SquareMatrix<u32> {
+        let mut m = SquareMatrix::with_storage(self.dim, self.storage());
+        m.labels = self.labels.clone();
+        m.index = self.index.clone();
+        m
+    }
+
+    /// Binary operations only make sense if both sides agree on what
+    ///  each row and column means.
+    fn check_labels(&self, other: &SquareMatrix<u32>) -> Result<(), &'static str> {
+        if self.labels != other.labels {
+            return Err("Matrix labels do not match.");
+        }
+        Ok(())
+    }
+
+    #[allow(dead_code)]
+    pub fn transpose(&self) -> SquareMatrix<u32> {
+        let mut m = self.zeroed();
+        for (i, j, v) in self.entries() {
+            m.set(j, i, v);
+        }
+        m
+    }
+
+    #[allow(dead_code)]
+    pub fn add(&self, other: &SquareMatrix<u32>) -> Result<SquareMatrix<u32>, &'static str> {
+        self.check_labels(other)?;
+        let mut m = self.clone();
+        for (i, j, v) in other.entries() {
+            m.add_to(i, j, v)?;
+        }
+        Ok(m)
+    }
+
+    /// Matrix product; only non-zero entries are visited, which keeps this
+    ///  cheap for the mostly-empty adjacency matrices we build.
+    #[allow(dead_code)]
+    pub fn multiply(&self, other: &SquareMatrix<u32>) -> Result<SquareMatrix<u32>, &'static str> {
+        self.check_labels(other)?;
+        let mut m = self.zeroed();
+        for (i, k, a) in self.entries() {
+            for (j, b) in other.row_entries(k) {
+                m.add_to(i, j, a.checked_mul(b).ok_or(OVERFLOW)?)?;
+            }
+        }
+        Ok(m)
+    }
+
+    /// Integer power by repeated squaring.  Fails if an entry overflows.
+    #[allow(dead_code)]
+    pub fn pow(&self, mut exp: u32) -> Result<SquareMatrix<u32>, &'static str> {
+        let mut result = self.zeroed();
+        for i in 0..self.dim {
+            result.set(i, i, 1);
+        }
+        let mut base = self.clone();
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = result.multiply(&base)?;
+            }
+            exp >>= 1;
+            if exp > 0 {
+                base = base.multiply(&base)?;
+            }
+        }
+        Ok(result)
+    }
+
+    #[allow(dead_code)]
+    pub fn is_zero(&self) -> bool {
+        self.entries().next().is_none()
+    }
+
+    /// A + A^2 + A^3 + ..., i.e. (I - A)^-1 - I, for a nilpotent A.
+    /// For an adjacency matrix entry (i, j) is the path-weighted number of
+    ///  ways to get from i to j, which for the bag rules is how many j bags
+    ///  end up inside an i bag.  Fails if the graph has a cycle, or if an
+    ///  entry overflows.
+    #[allow(dead_code)]
+    pub fn series_sum(&self) -> Result<SquareMatrix<u32>, &'static str> {
+        let mut total = self.zeroed();
+        let mut term = self.clone();
+        for _ in 0..self.dim {
+            if term.is_zero() {
+                return Ok(total);
+            }
+            total = total.add(&term)?;
+            term = term.multiply(self)?;
+        }
+        if term.is_zero() {
+            return Ok(total);
+        }
+        Err("Matrix is not nilpotent; the series does not converge.")
+    }
+
+    /// Fold every row into a single value, in label order
+    #[allow(dead_code)]
+    pub fn reduce_rows<F>(&self, init: u32, f: F) -> Vec<u32>
+        where F: Fn(u32, u32) -> u32 {
+        (0..self.dim)
+            .map(|i| (0..self.dim).fold(init, |acc, j| f(acc, self.get(i, j))))
+            .collect()
+    }
+
+    /// Fold every column into a single value, in label order
+    #[allow(dead_code)]
+    pub fn reduce_cols<F>(&self, init: u32, f: F) -> Vec<u32>
+        where F: Fn(u32, u32) -> u32 {
+        (0..self.dim)
+            .map(|j| (0..self.dim).fold(init, |acc, i| f(acc, self.get(i, j))))
+            .collect()
+    }
+
+    /// Sums are widened to u64 so that they can't overflow
+    #[allow(dead_code)]
+    pub fn row_sums(&self) -> Vec<u64> {
+        let mut sums = vec![0; self.dim];
+        for (i, _, v) in self.entries() {
+            sums[i] += v as u64;
+        }
+        sums
+    }
+
+    #[allow(dead_code)]
+    pub fn col_sums(&self) -> Vec<u64> {
+        let mut sums = vec![0; self.dim];
+        for (_, j, v) in self.entries() {
+            sums[j] += v as u64;
+        }
+        sums
+    }
+}
+
+/// Conversions to and from petgraph, so that the graph algorithms there
+///  (scc, dijkstra, toposort, ...) can be run on anything we parse into a
+///  labelled matrix.  Labels become node weights and non-zero values become
+///  edge weights.
+impl SquareMatrix<u32> {
+    #[allow(dead_code)]
+    pub fn to_petgraph(&self) -> Graph<String, u32> {
+        let mut g = Graph::with_capacity(self.dim, 0);
+        let nodes: Vec<NodeIndex> = self.labels.iter()
+            .map(|label| g.add_node(label.clone()))
+            .collect();
+
+        for (i, j, v) in self.entries() {
+            g.add_edge(nodes[i], nodes[j], v);
+        }
+        g
+    }
+
+    /// Build a matrix from a petgraph Graph.  Rows follow node index order.
+    /// Nodes sharing a label end up in the same row, and parallel edges have
+    ///  their weights summed, which fails if the sum overflows.
+    #[allow(dead_code)]
+    pub fn from_petgraph(g: &Graph<String, u32>) -> Result<SquareMatrix<u32>, &'static str> {
+        let mut m = SquareMatrix::new(0);
+        let mut rows = Vec::with_capacity(g.node_count());
+        for node in g.node_indices() {
+            let label = &g[node];
+            let row = match m.index_of(label) {
+                Some(x) => x,
+                None => {
+                    m.add_labelled_row(label.clone());
+                    m.dim-1
                 }
+            };
+            rows.push(row);
+        }
+
+        for edge in g.edge_references() {
+            let (i, j) = (rows[edge.source().index()], rows[edge.target().index()]);
+            m.add_to(i, j, *edge.weight())?;
+        }
+        Ok(m)
+    }
+
+    /// Borrowing view as a GraphMap keyed by label.  Unlabelled rows are
+    ///  skipped since they can't be told apart.
+    #[allow(dead_code)]
+    pub fn to_graphmap(&self) -> DiGraphMap<&str, u32> {
+        let mut g = DiGraphMap::with_capacity(self.dim, 0);
+        for label in self.labels.iter().filter(|x| !x.is_empty()) {
+            g.add_node(&label[..]);
+        }
+
+        for (i, j, v) in self.entries() {
+            if !self.labels[i].is_empty() && !self.labels[j].is_empty() {
+                g.add_edge(&self.labels[i][..], &self.labels[j][..], v);
+            }
+        }
+        g
+    }
+
+    /// Build a matrix from a GraphMap keyed by label; rows follow node
+    ///  insertion order.
+    #[allow(dead_code)]
+    pub fn from_graphmap(g: &DiGraphMap<&str, u32>) -> SquareMatrix<u32> {
+        let mut m = SquareMatrix::new(0);
+        for node in g.nodes() {
+            m.add_labelled_row(node.to_string());
+        }
+        for (a, b, &w) in g.all_edges() {
+            m.set_in_labelled_row(a.to_string(), b.to_string(), w);
+        }
+        m
+    }
+}
+
+/// Quote a CSV field if it needs it
+fn csv_field(field: &str) -> String {
+    if field.contains(',') || field.contains('"') || field.contains('\n') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Split one CSV line into fields, honouring double-quoted fields
+fn split_csv_line(line: &str) -> Result<Vec<String>, &'static str> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
             },
+            ('"', true) => quoted = false,
+            ('"', false) if field.is_empty() => quoted = true,
+            (',', false) => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
         }
     }
+    if quoted {
+        return Err("unterminated quoted field");
+    }
+    fields.push(field);
+    Ok(fields)
+}
 
+/// Text export and import, mostly for debugging: GraphViz DOT to look at a
+///  graph, CSV to save one and diff or reload it later.
+impl SquareMatrix<u32> {
+    /// GraphViz DOT with the values as edge labels.  Nodes named in
+    ///  `highlight` are filled in, as are the edges between them, e.g. pass
+    ///  everything reachable from "shiny gold" to see what it holds.
     #[allow(dead_code)]
-    pub fn size(&self) -> usize {
-        self.dim
+    pub fn to_dot(&self, highlight: &[String]) -> String {
+        let lit: Vec<bool> = self.labels.iter().map(|x| highlight.contains(x)).collect();
+        let mut dot = String::from("digraph {\n");
+
+        for (i, label) in self.labels.iter().enumerate() {
+            let label = label.replace('"', "\\\"");
+            if lit[i] {
+                dot.push_str(&format!("    {} [label=\"{}\", style=filled, fillcolor=gold];\n", i, label));
+            } else {
+                dot.push_str(&format!("    {} [label=\"{}\"];\n", i, label));
+            }
+        }
+        for (i, j, v) in self.entries() {
+            if lit[i] && lit[j] {
+                dot.push_str(&format!("    {} -> {} [label=\"{}\", color=red, penwidth=2];\n", i, j, v));
+            } else {
+                dot.push_str(&format!("    {} -> {} [label=\"{}\"];\n", i, j, v));
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
     }
 
-    pub fn index_of(&self, n: String) -> Option<usize> {
-        self.labels.iter().position(|r| r == &n)
+    /// CSV with a header row of labels and each row prefixed by its label.
+    ///  The header starts with an empty corner field, so with no labels at
+    ///  all it is an empty line.
+    #[allow(dead_code)]
+    pub fn to_csv(&self) -> String {
+        let mut csv: String = self.labels.iter().map(|x| format!(",{}", csv_field(x))).collect();
+        csv.push('\n');
+
+        for i in 0..self.dim {
+            let row: Vec<String> = self.get_row(i).iter().map(|x| x.to_string()).collect();
+            csv.push_str(&format!("{},{}\n", csv_field(&self.labels[i]), row.join(",")));
+        }
+        csv
     }
 
-    pub fn get_label(&self, n: usize) -> String {
-        self.labels[n].clone()
+    /// Parse the output of to_csv
+    #[allow(dead_code)]
+    pub fn from_csv(lines: Vec<String>) -> Result<SquareMatrix<u32>, String> {
+        let mut lines = lines.into_iter().enumerate();
+
+        let labels = match lines.next() {
+            Some((_, line)) if line.is_empty() => Vec::new(),
+            Some((n, line)) => split_csv_line(&line).map_err(|e| format!("line {}: {}", n+1, e))?[1..].to_vec(),
+            None => return Err("missing header row".to_string()),
+        };
+        let lines = lines.filter(|(_, x)| !x.is_empty());
+        let dim = labels.len();
+        let mut m = SquareMatrix::new(dim);
+        m.labels = labels;
+        m.reindex();
+
+        let mut row = 0;
+        for (n, line) in lines {
+            if row == dim {
+                return Err(format!("line {}: more rows than labels", n+1));
+            }
+            let fields = split_csv_line(&line).map_err(|e| format!("line {}: {}", n+1, e))?;
+            if fields.len() != dim + 1 {
+                return Err(format!("line {}: expected {} fields, found {}", n+1, dim+1, fields.len()));
+            }
+            if fields[0] != m.labels[row] {
+                return Err(format!("line {}: row label \"{}\" does not match column \"{}\"", n+1, fields[0], m.labels[row]));
+            }
+            for (col, field) in fields[1..].iter().enumerate() {
+                let value = field.trim().parse::<u32>()
+                    .map_err(|e| format!("line {}, column {}: {}", n+1, col+2, e))?;
+                m.set(row, col, value);
+            }
+            row += 1;
+        }
+
+        if row != dim {
+            return Err(format!("expected {} rows, found {}", dim, row));
+        }
+        Ok(m)
     }
 
-    pub fn get_values(&self) -> Vec<Vec<u32>> {
-        self.values.clone()
+    #[allow(dead_code)]
+    pub fn save_csv(&self, filename: &str) -> io::Result<()> {
+        fs::write(filename, self.to_csv())
     }
 
-    pub fn get_labels(&self) -> Vec<String> {
-        self.labels.clone()
+    #[allow(dead_code)]
+    pub fn load_csv(filename: &str) -> Result<SquareMatrix<u32>, String> {
+        let contents = fs::read_to_string(filename).map_err(|e| e.to_string())?;
+        SquareMatrix::from_csv(contents.lines().map(|x| x.to_string()).collect())
     }
 }
 
@@ -127,7 +637,7 @@ mod tests {
         let _m2 = SquareMatrix::new(0);
 
         assert_eq!(m.dim, 2);
-        assert_eq!(m.values, vec![vec![0; 2]; 2]);
+        assert_eq!(m.get_values().into_owned(), vec![vec![0; 2]; 2]);
     }
 
     #[test]
@@ -136,20 +646,20 @@ mod tests {
         m.resize(3);
 
         assert_eq!(m.dim, 3);
-        assert_eq!(m.values, vec![vec![0; 3]; 3]);
+        assert_eq!(m.get_values().into_owned(), vec![vec![0; 3]; 3]);
     }
 
     #[test]
     fn test_matrix_resize_with_values() {
         let mut m = SquareMatrix::new(2);
 
-        m.values[0][1] = 2;
+        m.set(0, 1, 2);
 
         m.resize(3);
 
         assert_eq!(m.dim, 3);
-        assert_eq!(m.values[0][1], 2);
-        assert_eq!(m.values[2][2], 0);
+        assert_eq!(m.get(0, 1), 2);
+        assert_eq!(m.get(2, 2), 0);
     }
 
     #[test]
@@ -159,17 +669,17 @@ mod tests {
         m.add_labelled_row("Peanut".to_string());
         m.add_labelled_row("Butter".to_string());
 
-        m.values[0][1] = 2;
-        m.get_labelled_row("Butter".to_string())[0] = 3;  // This won't set the original m
+        m.set(0, 1, 2);
         m.set_in_labelled_row_usize("Butter".to_string(), 1, 4);
         m.set_in_labelled_row("Peanut".to_string(), "Peanut".to_string(), 5);
 
         assert_eq!(m.dim, 2);
         assert_eq!(m.labels, vec!["Peanut".to_string(), "Butter".to_string()]);
 
-        assert_eq!(m.get_labelled_row("Peanut".to_string()), vec![5, 2]);
-        assert_eq!(m.get_labelled_row("Butter".to_string()), vec![0, 4]);
-        assert_eq!(m.values, vec![vec![5, 2], vec![0, 4]]);
+        assert_eq!(m.get_labelled_row("Peanut").as_deref(), Some(&[5, 2][..]));
+        assert_eq!(m.get_labelled_row("Butter").as_deref(), Some(&[0, 4][..]));
+        assert_eq!(m.get_labelled_row("Jelly"), None);
+        assert_eq!(m.get_values().into_owned(), vec![vec![5, 2], vec![0, 4]]);
     }
 
     #[test]
@@ -184,6 +694,247 @@ mod tests {
         m.set_in_labelled_row(butter.clone(), peanut.clone(), 0);
         m.set_in_labelled_row(butter.clone(), butter.clone(), 4);
 
-        assert_eq!(m.values, vec![vec![5, 2], vec![0, 4]]);
+        assert_eq!(m.get_values().into_owned(), vec![vec![5, 2], vec![0, 4]]);
+    }
+
+    #[test]
+    fn test_matrix_label_index() {
+        let mut m = SquareMatrix::new(1);
+
+        m.add_labelled_row("Peanut".to_string());
+        m.set_in_labelled_row("Butter".to_string(), "Jelly".to_string(), 7);
+
+        assert_eq!(m.index_of(""), None);
+        assert_eq!(m.index_of("Peanut"), Some(1));
+        assert_eq!(m.index_of("Butter"), Some(2));
+        assert_eq!(m.index_of("Jelly"), Some(3));
+        assert_eq!(m.get_labelled("Butter", "Jelly"), Some(7));
+        assert_eq!(m.get_labelled("Butter", "Toast"), None);
+
+        m.resize(2);
+
+        assert_eq!(m.index_of("Peanut"), Some(1));
+        assert_eq!(m.index_of("Butter"), None);
+        assert_eq!(&m.get_row(1)[..], &[0, 0]);
+    }
+
+    /// Small version of the day 7 example:
+    ///  shiny gold -> 1 dark olive, 2 vibrant plum
+    ///  dark olive -> 3 faded blue
+    ///  vibrant plum -> 5 faded blue
+    fn bag_matrix() -> SquareMatrix<u32> {
+        let mut m = SquareMatrix::new(0);
+        m.set_in_labelled_row("shiny gold".to_string(), "dark olive".to_string(), 1);
+        m.set_in_labelled_row("shiny gold".to_string(), "vibrant plum".to_string(), 2);
+        m.set_in_labelled_row("dark olive".to_string(), "faded blue".to_string(), 3);
+        m.set_in_labelled_row("vibrant plum".to_string(), "faded blue".to_string(), 5);
+        m
+    }
+
+    #[test]
+    fn test_matrix_linear_algebra() {
+        let m = bag_matrix();
+        let id = SquareMatrix::identity(m.get_labels());
+
+        assert_eq!(m.multiply(&id).unwrap(), m);
+        assert_eq!(m.pow(0).unwrap(), id);
+        assert_eq!(m.pow(1).unwrap(), m);
+        assert_eq!(m.transpose().transpose(), m);
+        assert_eq!(m.transpose().get_labelled("faded blue", "vibrant plum"), Some(5));
+
+        let m2 = m.pow(2).unwrap();
+        assert_eq!(m2, m.multiply(&m).unwrap());
+        assert_eq!(m2.get_labelled("shiny gold", "faded blue"), Some(13));
+        assert!(m.pow(3).unwrap().is_zero());
+
+        assert_eq!(m.add(&m).unwrap().get_labelled("shiny gold", "vibrant plum"), Some(4));
+        assert!(m.add(&SquareMatrix::new(4)).is_err());
+
+        assert_eq!(m.row_sums(), vec![3, 3, 5, 0]);
+        assert_eq!(m.col_sums(), vec![0, 1, 2, 8]);
+        assert_eq!(m.reduce_rows(0, u32::max), vec![2, 3, 5, 0]);
+    }
+
+    #[test]
+    fn test_matrix_series_sum() {
+        let m = bag_matrix();
+        let total = m.series_sum().unwrap();
+        let shiny_gold = total.index_of("shiny gold").unwrap();
+
+        // 1 + 2 + 3 + 10 bags inside a shiny gold one
+        assert_eq!(total.row_sums()[shiny_gold], 16);
+
+        let mut cyclic = m.clone();
+        cyclic.set_in_labelled_row("faded blue".to_string(), "shiny gold".to_string(), 1);
+        assert!(cyclic.series_sum().is_err());
+    }
+
+    #[test]
+    fn test_matrix_overflow() {
+        // 70000 * 70000 paths don't fit in a u32
+        let mut m = SquareMatrix::new(0);
+        m.set_in_labelled_row("a".to_string(), "b".to_string(), 70000);
+        m.set_in_labelled_row("b".to_string(), "c".to_string(), 70000);
+        assert_eq!(m.pow(1).unwrap(), m);
+        assert_eq!(m.pow(2), Err(OVERFLOW));
+        assert_eq!(m.series_sum(), Err(OVERFLOW));
+
+        let mut big = m.clone();
+        big.set(0, 1, u32::MAX);
+        assert_eq!(big.add(&m), Err(OVERFLOW));
+
+        // Sums are wide enough for any row
+        big.set(0, 2, u32::MAX);
+        assert_eq!(big.row_sums(), vec![2 * u32::MAX as u64, 70000, 0]);
+        assert_eq!(big.col_sums(), vec![0, u32::MAX as u64, u32::MAX as u64 + 70000]);
+
+        let mut g = Graph::new();
+        let (a, b) = (g.add_node("a".to_string()), g.add_node("b".to_string()));
+        g.add_edge(a, b, u32::MAX);
+        g.add_edge(a, b, 1);
+        assert_eq!(SquareMatrix::from_petgraph(&g), Err(OVERFLOW));
+    }
+
+    #[test]
+    fn test_matrix_petgraph_roundtrip() {
+        let m = bag_matrix();
+
+        let g = m.to_petgraph();
+        assert_eq!(g.node_count(), 4);
+        assert_eq!(g.edge_count(), 4);
+        assert_eq!(SquareMatrix::from_petgraph(&g).unwrap(), m);
+
+        let gm = m.to_graphmap();
+        assert_eq!(gm.edge_weight("vibrant plum", "faded blue"), Some(&5));
+        assert_eq!(SquareMatrix::from_graphmap(&gm), m);
+    }
+
+    #[test]
+    fn test_matrix_petgraph_algorithms() {
+        let mut m = bag_matrix();
+
+        let g = m.to_petgraph();
+        let order: Vec<&str> = petgraph::algo::toposort(&g, None).unwrap()
+            .into_iter()
+            .map(|n| &g[n][..])
+            .collect();
+        assert_eq!(order.first(), Some(&"shiny gold"));
+        assert_eq!(order.last(), Some(&"faded blue"));
+
+        let start = g.node_indices().find(|&n| g[n] == "shiny gold").unwrap();
+        let costs = petgraph::algo::dijkstra(&g, start, None, |e| *e.weight());
+        let blue = g.node_indices().find(|&n| g[n] == "faded blue").unwrap();
+        assert_eq!(costs[&blue], 4);
+
+        m.set_in_labelled_row("faded blue".to_string(), "shiny gold".to_string(), 1);
+        let sccs = petgraph::algo::kosaraju_scc(&m.to_graphmap());
+        assert_eq!(sccs.len(), 1);
+        assert_eq!(sccs[0].len(), 4);
+    }
+
+    #[test]
+    fn test_matrix_to_dot() {
+        let m = bag_matrix();
+        let dot = m.to_dot(&[]);
+
+        assert!(dot.starts_with("digraph {\n"));
+        assert!(dot.contains("    0 [label=\"shiny gold\"];\n"));
+        assert!(dot.contains("    2 -> 3 [label=\"5\"];\n"));
+        assert_eq!(dot.matches("->").count(), 4);
+
+        let lit = m.to_dot(&["vibrant plum".to_string(), "faded blue".to_string()]);
+        assert!(lit.contains("    2 [label=\"vibrant plum\", style=filled, fillcolor=gold];\n"));
+        assert!(lit.contains("    2 -> 3 [label=\"5\", color=red, penwidth=2];\n"));
+        assert!(lit.contains("    0 -> 2 [label=\"2\"];\n"));
+    }
+
+    #[test]
+    fn test_matrix_csv_roundtrip() {
+        let mut m = bag_matrix();
+        m.set_in_labelled_row("odd, \"quoted\" bag".to_string(), "shiny gold".to_string(), 7);
+
+        let csv = m.to_csv();
+        assert!(csv.starts_with(",shiny gold,dark olive,vibrant plum,faded blue,\"odd, \"\"quoted\"\" bag\"\n"));
+
+        let lines: Vec<String> = csv.lines().map(|x| x.to_string()).collect();
+        assert_eq!(SquareMatrix::from_csv(lines).unwrap(), m);
+
+        let filename = std::env::temp_dir().join("aoc2020_test_matrix.csv");
+        let filename = filename.to_str().unwrap();
+        m.save_csv(filename).unwrap();
+        assert_eq!(SquareMatrix::load_csv(filename).unwrap(), m);
+        let _ = fs::remove_file(filename);
+
+        // No labels, and a single empty one, are different matrices
+        let empty: SquareMatrix<u32> = SquareMatrix::new(0);
+        assert_eq!(empty.to_csv(), "\n");
+        let lines: Vec<String> = empty.to_csv().lines().map(|x| x.to_string()).collect();
+        assert_eq!(SquareMatrix::from_csv(lines).unwrap(), empty);
+        let mut single: SquareMatrix<u32> = SquareMatrix::new(1);
+        single.set(0, 0, 3);
+        assert_eq!(single.to_csv(), ",\n,3\n");
+        let lines: Vec<String> = single.to_csv().lines().map(|x| x.to_string()).collect();
+        assert_eq!(SquareMatrix::from_csv(lines).unwrap(), single);
+    }
+
+    #[test]
+    fn test_matrix_csv_errors() {
+        let to_lines = |x: &[&str]| x.iter().map(|y| y.to_string()).collect::<Vec<String>>();
+
+        assert!(SquareMatrix::from_csv(Vec::new()).is_err());
+        assert_eq!(
+            SquareMatrix::from_csv(to_lines(&[",a,b", "a,0,1", "b,0,x"])).unwrap_err(),
+            "line 3, column 3: invalid digit found in string"
+        );
+        assert_eq!(
+            SquareMatrix::from_csv(to_lines(&[",a,b", "a,0,1"])).unwrap_err(),
+            "expected 2 rows, found 1"
+        );
+        assert_eq!(
+            SquareMatrix::from_csv(to_lines(&[",a,b", "b,0,1", "a,0,0"])).unwrap_err(),
+            "line 2: row label \"b\" does not match column \"a\""
+        );
+        assert_eq!(
+            SquareMatrix::from_csv(to_lines(&[",a", "a,0,1"])).unwrap_err(),
+            "line 2: expected 2 fields, found 3"
+        );
+    }
+
+    #[test]
+    fn test_matrix_sparse_storage() {
+        let mut m = SquareMatrix::with_storage(0, Storage::Sparse);
+
+        m.set_in_labelled_row("shiny gold".to_string(), "dark olive".to_string(), 1);
+        m.set_in_labelled_row("shiny gold".to_string(), "vibrant plum".to_string(), 2);
+        m.set_in_labelled_row("dark olive".to_string(), "faded blue".to_string(), 3);
+        m.set_in_labelled_row("vibrant plum".to_string(), "faded blue".to_string(), 5);
+
+        let dense = bag_matrix();
+        assert_eq!(m.storage(), Storage::Sparse);
+        assert_eq!(dense.storage(), Storage::Dense);
+        assert_eq!(m, dense);
+        assert_eq!(m.nonzero_count(), 4);
+        assert_eq!(m.get_values(), dense.get_values());
+        assert_eq!(m.get_labelled_row("shiny gold").as_deref(), Some(&[0, 1, 2, 0][..]));
+        assert_eq!(m.row_entries(0).collect::<Vec<_>>(), vec![(1, 1), (2, 2)]);
+        assert_eq!(m.entries().collect::<Vec<_>>(), dense.entries().collect::<Vec<_>>());
+
+        // Operations keep the storage of the left-hand side
+        assert_eq!(m.pow(2).unwrap().storage(), Storage::Sparse);
+        assert_eq!(m.pow(2), dense.pow(2));
+        assert_eq!(m.transpose(), dense.transpose());
+        assert_eq!(m.series_sum().unwrap(), dense.series_sum().unwrap());
+        assert_eq!(m.row_sums(), dense.row_sums());
+        assert_eq!(m.col_sums(), dense.col_sums());
+        assert_eq!(m.to_csv(), dense.to_csv());
+        assert_eq!(m.to_dot(&[]), dense.to_dot(&[]));
+
+        // Zeroing an entry drops it, shrinking drops the columns
+        m.set(0, 1, 0);
+        assert_eq!(m.nonzero_count(), 3);
+        m.resize(3);
+        assert_eq!(m.nonzero_count(), 1);
+        assert_eq!(m.index_of("faded blue"), None);
+        assert_eq!(m.get_values().into_owned(), vec![vec![0, 0, 2], vec![0; 3], vec![0; 3]]);
     }
 }
\ No newline at end of file
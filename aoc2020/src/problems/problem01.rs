@@ -1,4 +1,3 @@
-use itertools::Itertools;
 use log::{trace, debug, info, warn, error}; // trace, debug, info, warn, error
 use crate::util::RetType;
 
@@ -11,34 +10,152 @@ fn _get_rid_of_log_unused_import_warnings() {
     error!("Example error.");
 }
 
-fn naive_find_sum_equal_to(input_vector: Vec<u32>, target_value: u32) -> Result<(u32, u32), &'static str> {
-    for (i, x) in input_vector.iter().enumerate() {
-        for (j, y) in input_vector.iter().enumerate() {
-            if i == j {
-                continue;
+/// The sum the elves are after
+pub const EXPENSE_TARGET: u64 = 2020;
+
+/// Entries of the expense report that add up to the target, in report order
+#[derive(Clone, Debug, PartialEq)]
+pub struct Solution {
+    pub entries: Vec<u32>,
+    /// Line of each entry in the report, counting from 0
+    pub indices: Vec<usize>,
+}
+
+impl Solution {
+    pub fn product(&self) -> u64 {
+        self.entries.iter().map(|&x| x as u64).product()
+    }
+}
+
+/// Find k entries that add up to target, using each entry at most once.
+///  Sorts, then fixes entries one at a time and closes with two pointers, so
+///  this is O(n^(k-1)) rather than trying every combination.  With `all` set
+///  every solution is returned (different lines, even if the numbers are the
+///  same), ordered by line; otherwise just the first found.
+pub fn k_sum(values: &[u32], k: usize, target: u64, all: bool) -> Vec<Solution> {
+    let mut sorted: Vec<(u64, usize)> = values.iter().enumerate().map(|(i, &x)| (x as u64, i)).collect();
+    sorted.sort_unstable();
+
+    let mut found = Vec::new();
+    let mut chosen = Vec::with_capacity(k);
+    k_sum_from(&sorted, 0, k, target, all, &mut chosen, &mut |picked| {
+        let mut indices: Vec<usize> = picked.iter().map(|&p| sorted[p].1).collect();
+        indices.sort_unstable();
+        found.push(Solution { entries: indices.iter().map(|&i| values[i]).collect(), indices });
+    });
+
+    found.sort_by(|a, b| a.indices.cmp(&b.indices));
+    found
+}
+
+/// Pick k more of sorted[from..] (positions into sorted) adding up to
+///  target.  Returns true once it should stop looking.
+fn k_sum_from(sorted: &[(u64, usize)], from: usize, k: usize, target: u64, all: bool,
+              chosen: &mut Vec<usize>, report: &mut dyn FnMut(&[usize])) -> bool {
+    let n = sorted.len();
+    match k {
+        0 => {
+            if target == 0 {
+                report(chosen);
+                return !all;
             }
-            if x+y == target_value {
-                return Ok((*x, *y));
+            false
+        },
+        1 => {
+            for (p, &(value, _)) in sorted.iter().enumerate().skip(from) {
+                if value == target {
+                    chosen.push(p);
+                    report(chosen);
+                    chosen.pop();
+                    if !all {
+                        return true;
+                    }
+                }
             }
-        }
+            false
+        },
+        2 => {
+            if n < from + 2 {
+                return false;
+            }
+            let (mut lo, mut hi) = (from, n - 1);
+            while lo < hi {
+                let sum = sorted[lo].0 + sorted[hi].0;
+                if sum < target {
+                    lo += 1;
+                } else if sum > target {
+                    hi -= 1;
+                } else {
+                    // Every pairing of the equal values at each end works
+                    let lo_end = (lo..=hi).take_while(|&p| sorted[p].0 == sorted[lo].0).last().unwrap();
+                    let hi_start = (lo..=hi).rev().take_while(|&p| sorted[p].0 == sorted[hi].0).last().unwrap();
+                    let pairs: Vec<(usize, usize)> = if sorted[lo].0 == sorted[hi].0 {
+                        (lo..=hi).flat_map(|a| (a + 1..=hi).map(move |b| (a, b))).collect()
+                    } else {
+                        (lo..=lo_end).flat_map(|a| (hi_start..=hi).map(move |b| (a, b))).collect()
+                    };
+                    for (a, b) in pairs {
+                        chosen.push(a);
+                        chosen.push(b);
+                        report(chosen);
+                        chosen.truncate(chosen.len() - 2);
+                        if !all {
+                            return true;
+                        }
+                    }
+                    if sorted[lo].0 == sorted[hi].0 {
+                        break;
+                    }
+                    lo = lo_end + 1;
+                    hi = hi_start - 1;
+                }
+            }
+            false
+        },
+        _ => {
+            for p in from..n {
+                // Everything after p is at least as big
+                if sorted[p].0 * k as u64 > target {
+                    break;
+                }
+                chosen.push(p);
+                let done = k_sum_from(sorted, p + 1, k - 1, target - sorted[p].0, all, chosen, report);
+                chosen.pop();
+                if done {
+                    return true;
+                }
+            }
+            false
+        },
     }
-    return Err("No matching pair found.");
 }
 
-fn find_sum_equal_to(input_vector: Vec<u32>, num_combo: usize, target_value: u32) -> Result<u32, &'static str> {
-    let combos = input_vector.iter().combinations(num_combo);
-    for entry in combos {
-        let entry_it = entry.clone();
-        if entry_it.into_iter().sum::<u32>() == target_value {
-            let mut prod = 1;
-            for x in entry {
-                trace!("Entry: {}", x);
-                prod *= x;
-            }
-            return Ok(prod);
+/// Parse the report, one number per line
+pub fn parse_report(input: &[String]) -> Result<Vec<u32>, String> {
+    input.iter()
+        .enumerate()
+        .filter(|(_, x)| !x.trim().is_empty())
+        .map(|(i, x)| x.trim().parse::<u32>().map_err(|_| format!("line {}: bad entry `{}`", i + 1, x)))
+        .collect()
+}
+
+/// Product of the first k entries that add up to the target, or Failed
+///  if the report is bad or no entries do
+fn expense_product(input: Vec<String>, k: usize) -> RetType {
+    let values = match parse_report(&input) {
+        Ok(values) => values,
+        Err(e) => {
+            error!("{}", e);
+            return RetType::Failed;
+        }
+    };
+    match k_sum(&values, k, EXPENSE_TARGET, false).first() {
+        Some(solution) => RetType::U64(solution.product()),
+        None => {
+            error!("No {} entries add up to {}", k, EXPENSE_TARGET);
+            RetType::Failed
         }
     }
-    return Err("No matching pair found.");
 }
 
 /**
@@ -50,14 +167,7 @@ fn find_sum_equal_to(input_vector: Vec<u32>, num_combo: usize, target_value: u32
  *  then multiply those two numbers together.
  */
 pub fn problem_011(input: Vec<String>) -> RetType {
-    let parsed_input: Vec<u32> = input
-        .into_iter()
-        .map(|x| x.parse::<u32>().unwrap())
-        .collect()
-    ;
-    let entry = naive_find_sum_equal_to(parsed_input, 2020).unwrap();
-    let result = entry.0 * entry.1;
-    return RetType::U32(result);
+    expense_product(input, 2)
 }
 
 /**
@@ -68,17 +178,13 @@ pub fn problem_011(input: Vec<String>) -> RetType {
  *  expense report that meet the same criteria.
  */
 pub fn problem_012(input: Vec<String>) -> RetType {
-    let parsed_input: Vec<u32> = input
-        .into_iter()
-        .map(|x| x.parse::<u32>().unwrap())
-        .collect()
-    ;
-    return RetType::U32(find_sum_equal_to(parsed_input, 3, 2020).unwrap());
+    expense_product(input, 3)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use itertools::Itertools;
 
     fn init() {
         match env_logger::try_init() {
@@ -92,54 +198,61 @@ mod tests {
     }
 
     #[test]
-    /// For example, suppose your expense report contained the following:
-    ///     
-    /// 1721
-    /// 979
-    /// 366
-    /// 299
-    /// 675
-    /// 1456
-    ///
-    /// In this list, the two entries that sum to 2020 are 1721 and 299. Multiplying them together produces 
-    ///  1721 * 299 = 514579, so the correct answer is 514579.
-    fn test_naive_find_sum_equal_to() {
+    fn test_k_sum() {
         init();
         let input_vec = vec![1721, 979, 366, 299, 675, 1456];
-        let result = naive_find_sum_equal_to(input_vec, 2020);
-        match result {
-            Ok((1721, 299)) => {
-                assert!(true);
-            },
-            Ok(_) => {
-                error!("Wrong result: {} {}", result.unwrap().0, result.unwrap().1);
-                assert!(false);
-            },
-            Err(_) => {
-                assert!(false);
-            }
-        }
+        let pair = k_sum(&input_vec, 2, 2020, false);
+        assert_eq!(pair, vec![Solution { entries: vec![1721, 299], indices: vec![0, 3] }]);
+        assert_eq!(pair[0].product(), 514579);
+        let triple = k_sum(&input_vec, 3, 2020, false);
+        assert_eq!(triple[0].indices, vec![1, 2, 4]);
+        assert_eq!(triple[0].product(), 241861950);
+
+        assert!(k_sum(&input_vec, 2, 5, true).is_empty());
+        assert!(k_sum(&input_vec, 7, 2020, true).is_empty());
+        assert_eq!(k_sum(&input_vec, 1, 366, false)[0].indices, vec![2]);
+        assert_eq!(k_sum(&input_vec, 0, 0, false), vec![Solution { entries: vec![], indices: vec![] }]);
     }
 
     #[test]
-    /// Using the above example again, the three entries that sum to 2020 are 979, 366, and 675. 
-    ///  Multiplying them together produces the answer, 241861950.
-    fn test_find_sum_equal_to() {
+    fn test_k_sum_all() {
         init();
-        let input_vec = vec![1721, 979, 366, 299, 675, 1456];
-        let result = find_sum_equal_to(input_vec, 3, 2020);
-        match result {
-            Ok(241861950) => {
-                assert!(true);
-            },
-            Ok(_) => {
-                error!("Wrong result: {}", result.unwrap());
-                assert!(false);
-            },
-            Err(_) => {
-                assert!(false);
+        // Repeated numbers count as different entries
+        let input_vec = vec![5, 5, 5, 1, 9, 10];
+        let pairs: Vec<Vec<usize>> = k_sum(&input_vec, 2, 10, true).into_iter().map(|s| s.indices).collect();
+        assert_eq!(pairs, vec![vec![0, 1], vec![0, 2], vec![1, 2], vec![3, 4]]);
+
+        let input_vec = vec![1, 2, 3, 4, 5, 6];
+        let triples: Vec<Vec<u32>> = k_sum(&input_vec, 3, 10, true).into_iter().map(|s| s.entries).collect();
+        assert_eq!(triples, vec![vec![1, 3, 6], vec![1, 4, 5], vec![2, 3, 5]]);
+
+        // Same answers as trying every combination
+        for k in 1..=4 {
+            for target in 0..25 {
+                let expected = input_vec.iter().enumerate().combinations(k)
+                    .filter(|c| c.iter().map(|(_, &x)| x as u64).sum::<u64>() == target)
+                    .count();
+                assert_eq!(k_sum(&input_vec, k, target, true).len(), expected, "k {} target {}", k, target);
             }
         }
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_parse_report() {
+        let input: Vec<String> = vec!["12".to_string(), "".to_string(), "7".to_string()];
+        assert_eq!(parse_report(&input), Ok(vec![12, 7]));
+        assert_eq!(parse_report(&["x1".to_string()]), Err("line 1: bad entry `x1`".to_string()));
+    }
+
+    #[test]
+    fn test_problem_01() {
+        init();
+        let input: Vec<String> = [1721, 979, 366, 299, 675, 1456].iter().map(|x| x.to_string()).collect();
+        assert_eq!(problem_011(input.clone()), RetType::U64(514579));
+        assert_eq!(problem_012(input), RetType::U64(241861950));
+
+        // Nothing adds up, or nothing to add up
+        assert_eq!(problem_011(vec!["1".to_string(), "2".to_string()]), RetType::Failed);
+        assert_eq!(problem_012(vec!["x".to_string()]), RetType::Failed);
+    }
+}
@@ -11,17 +11,48 @@ fn _get_rid_of_log_unused_import_warnings() {
     error!("Example error.");
 }
 
-/// Parse a password line
-///  1-3 a: abcde
-///  1-3 b: cdefg
-fn parse_password_line(input_str: String) -> (usize, usize, char, String) {
-    let re = Regex::new(r"(\d+)-(\d+)\s(.):\s(.*)").unwrap();
-    let cap = re.captures(&input_str).unwrap();
+/// One line of the database: the policy numbers and letter, and the password
+#[derive(Clone, Debug, PartialEq)]
+pub struct PasswordEntry {
+    pub low: usize,
+    pub high: usize,
+    pub letter: char,
+    pub password: String,
+}
+
+/// Reads password lines, building the pattern once for the whole database
+struct LineParser {
+    re: Regex,
+}
+
+impl LineParser {
+    fn new() -> LineParser {
+        LineParser { re: Regex::new(r"^(\d+)-(\d+)\s(.):\s(.*)$").unwrap() }
+    }
+
+    /// Parse a password line
+    ///  1-3 a: abcde
+    ///  1-3 b: cdefg
+    fn parse(&self, input_str: &str) -> Result<PasswordEntry, String> {
+        let cap = self.re.captures(input_str).ok_or_else(|| format!("can't parse `{}`", input_str))?;
 
-    return (cap[1].parse::<usize>().unwrap(), 
-            cap[2].parse::<usize>().unwrap(), 
-            cap[3].parse::<char>().unwrap(), 
-            cap[4].parse::<String>().unwrap());
+        Ok(PasswordEntry {
+            low: cap[1].parse::<usize>().map_err(|e| e.to_string())?,
+            high: cap[2].parse::<usize>().map_err(|e| e.to_string())?,
+            letter: cap[3].parse::<char>().unwrap(),
+            password: cap[4].to_string(),
+        })
+    }
+}
+
+/// A rule a password has to follow
+pub trait PasswordPolicy {
+    /// Ok if the password passes, otherwise why it doesn't
+    fn check(&self, entry: &PasswordEntry) -> Result<(), String>;
+
+    fn is_valid(&self, entry: &PasswordEntry) -> bool {
+        self.check(entry).is_ok()
+    }
 }
 
 /// 1-3 a: abcde
@@ -32,17 +63,16 @@ fn parse_password_line(input_str: String) -> (usize, usize, char, String) {
 ///  not; it contains no instances of b, but needs at least 1. The first and 
 ///  third passwords are valid: they contain one a or nine c, both within the 
 ///  limits of their respective policies.
-fn is_valid_sled_password_tuple(entry: (usize, usize, char, String)) -> bool {
-    let min_count = entry.0;
-    let max_count = entry.1;
-    let letter    = entry.2;
-    let pass_str  = entry.3;
+pub struct SledPolicy;
 
-    let num_matches = pass_str.matches(letter).count();
-    if num_matches >= min_count && num_matches <= max_count {
-        return true;
-    } 
-    return false;
+impl PasswordPolicy for SledPolicy {
+    fn check(&self, entry: &PasswordEntry) -> Result<(), String> {
+        let num_matches = entry.password.matches(entry.letter).count();
+        if num_matches >= entry.low && num_matches <= entry.high {
+            return Ok(());
+        }
+        Err(format!("sled: {} {}, need {}-{}", num_matches, entry.letter, entry.low, entry.high))
+    }
 }
 
 /// Each policy actually describes two positions in the password, where 1 means the 
@@ -54,17 +84,168 @@ fn is_valid_sled_password_tuple(entry: (usize, usize, char, String)) -> bool {
 ///  1-3 a: abcde is valid: position 1 contains a and position 3 does not.
 ///  1-3 b: cdefg is invalid: neither position 1 nor position 3 contains b.
 ///  2-9 c: ccccccccc is invalid: both position 2 and position 9 contain c.
-fn is_valid_toboggan_password_tuple(entry: (usize, usize, char, String)) -> bool {
-    let yes_index = entry.0 - 1;
-    let no_index  = entry.1 - 1;
-    let letter    = entry.2;
-    let pass_str  = entry.3.as_bytes();
+pub struct TobogganPolicy;
 
-    if (pass_str[yes_index] as char == letter) ^ (pass_str[no_index] as char == letter) {
-        return true;
+impl PasswordPolicy for TobogganPolicy {
+    fn check(&self, entry: &PasswordEntry) -> Result<(), String> {
+        // Positions past the end (or 0) just don't hold the letter
+        let holds = |pos: usize| pos > 0 && entry.password.chars().nth(pos - 1) == Some(entry.letter);
+        match (holds(entry.low), holds(entry.high)) {
+            (true, false) | (false, true) => Ok(()),
+            (true, true) => Err(format!("toboggan: {} at both positions {} and {}", entry.letter, entry.low, entry.high)),
+            (false, false) => Err(format!("toboggan: {} at neither position {} nor {}", entry.letter, entry.low, entry.high)),
+        }
     }
+}
+
+/// The password has to match a regular expression
+pub struct RegexPolicy(pub Regex);
 
-    return false;
+impl PasswordPolicy for RegexPolicy {
+    fn check(&self, entry: &PasswordEntry) -> Result<(), String> {
+        if self.0.is_match(&entry.password) {
+            return Ok(());
+        }
+        Err(format!("regex: doesn't match `{}`", self.0.as_str()))
+    }
+}
+
+pub struct MinLength(pub usize);
+
+impl PasswordPolicy for MinLength {
+    fn check(&self, entry: &PasswordEntry) -> Result<(), String> {
+        let len = entry.password.chars().count();
+        if len >= self.0 {
+            return Ok(());
+        }
+        Err(format!("min length: {} characters, need {}", len, self.0))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CharClass {
+    Lower,
+    Upper,
+    Digit,
+    Symbol,
+}
+
+impl CharClass {
+    fn contains(&self, c: char) -> bool {
+        match self {
+            CharClass::Lower => c.is_lowercase(),
+            CharClass::Upper => c.is_uppercase(),
+            CharClass::Digit => c.is_ascii_digit(),
+            CharClass::Symbol => !c.is_alphanumeric() && !c.is_whitespace(),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            CharClass::Lower => "lower",
+            CharClass::Upper => "upper",
+            CharClass::Digit => "digit",
+            CharClass::Symbol => "symbol",
+        }
+    }
+
+    pub fn parse(name: &str) -> Result<CharClass, String> {
+        match name {
+            "lower" => Ok(CharClass::Lower),
+            "upper" => Ok(CharClass::Upper),
+            "digit" => Ok(CharClass::Digit),
+            "symbol" => Ok(CharClass::Symbol),
+            _ => Err(format!("unknown character class `{}`", name)),
+        }
+    }
+}
+
+/// At least one character from each of the classes
+pub struct CharClasses(pub Vec<CharClass>);
+
+impl PasswordPolicy for CharClasses {
+    fn check(&self, entry: &PasswordEntry) -> Result<(), String> {
+        let missing: Vec<&str> = self.0.iter()
+            .filter(|class| !entry.password.chars().any(|c| class.contains(c)))
+            .map(|class| class.name())
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        Err(format!("classes: no {}", missing.join(", no ")))
+    }
+}
+
+/// Every one of the policies, reporting the first that fails
+pub struct AllOf(pub Vec<Box<dyn PasswordPolicy>>);
+
+impl PasswordPolicy for AllOf {
+    fn check(&self, entry: &PasswordEntry) -> Result<(), String> {
+        self.0.iter().try_for_each(|policy| policy.check(entry))
+    }
+}
+
+/// Put a policy together from command line style settings.  `base` is
+///  sled, toboggan or none; classes is a comma separated list.
+pub fn build_policy(base: &str, min_length: Option<usize>, regex: Option<&str>, classes: Option<&str>) -> Result<AllOf, String> {
+    let mut policies: Vec<Box<dyn PasswordPolicy>> = Vec::new();
+    match base {
+        "sled" => policies.push(Box::new(SledPolicy)),
+        "toboggan" => policies.push(Box::new(TobogganPolicy)),
+        "none" => {},
+        _ => return Err(format!("unknown policy `{}`", base)),
+    }
+    if let Some(len) = min_length {
+        policies.push(Box::new(MinLength(len)));
+    }
+    if let Some(re) = regex {
+        policies.push(Box::new(RegexPolicy(Regex::new(re).map_err(|e| e.to_string())?)));
+    }
+    if let Some(names) = classes {
+        let classes = names.split(',').map(|x| CharClass::parse(x.trim())).collect::<Result<Vec<_>, _>>()?;
+        policies.push(Box::new(CharClasses(classes)));
+    }
+    Ok(AllOf(policies))
+}
+
+/// Lines that fail the policy, with what rejected them.  Lines that can't be
+///  parsed are listed too.
+pub fn violation_report(input: &[String], policy: &dyn PasswordPolicy) -> Vec<String> {
+    let parser = LineParser::new();
+    input.iter()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let why = match parser.parse(line) {
+                Ok(entry) => policy.check(&entry).err()?,
+                Err(e) => e,
+            };
+            Some(format!("line {}: {} -- {}", i + 1, line, why))
+        })
+        .collect()
+}
+
+/// How many lines pass the policy.  A line that can't be parsed is an
+///  error rather than just another invalid password.
+fn count_valid(input: &[String], policy: &dyn PasswordPolicy) -> Result<u32, String> {
+    let parser = LineParser::new();
+    let mut count = 0;
+    for (i, line) in input.iter().enumerate() {
+        let entry = parser.parse(line).map_err(|e| format!("line {}: {}", i + 1, e))?;
+        if policy.is_valid(&entry) {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+fn count_or_fail(input: &[String], policy: &dyn PasswordPolicy) -> RetType {
+    match count_valid(input, policy) {
+        Ok(count) => RetType::U32(count),
+        Err(e) => {
+            error!("Couldn't read the password database: {}", e);
+            RetType::Failed
+        }
+    }
 }
 
 /**
@@ -75,14 +256,7 @@ fn is_valid_toboggan_password_tuple(entry: (usize, usize, char, String)) -> bool
  * How many passwords are valid according to their policies?
  */
 pub fn problem_021(input: Vec<String>) -> RetType {
-    let mut count: u32 = 0;
-    for line in input.into_iter() {
-        if is_valid_sled_password_tuple(parse_password_line(line)) {
-            count += 1;
-        }
-    }
-
-    return RetType::U32(count);
+    count_or_fail(&input, &SledPolicy)
 }
 
 /**
@@ -94,14 +268,7 @@ pub fn problem_021(input: Vec<String>) -> RetType {
  * How many passwords are valid according to the new interpretation of the policies?
  */
 pub fn problem_022(input: Vec<String>) -> RetType {
-    let mut count: u32 = 0;
-    for line in input.into_iter() {
-        if is_valid_toboggan_password_tuple(parse_password_line(line)) {
-            count += 1;
-        }
-    }
-
-    return RetType::U32(count);
+    count_or_fail(&input, &TobogganPolicy)
 }
 
 #[cfg(test)]
@@ -119,40 +286,88 @@ mod tests {
         }
     }
 
+    fn entry(low: usize, high: usize, letter: char, password: &str) -> PasswordEntry {
+        PasswordEntry { low, high, letter, password: String::from(password) }
+    }
+
     #[test]
     fn test_parse_password_line() {
         init();
-        let string_one = String::from("1-3 a: abcde");
-        let string_two = String::from("1-3 b: cdefg");
-        let string_three = String::from("2-9 c: ccccccccc");
-        let string_four = String::from("12-19 d: goodjorb");
+        let parser = LineParser::new();
+        assert_eq!(parser.parse("1-3 a: abcde"), Ok(entry(1, 3, 'a', "abcde")));
+        assert_eq!(parser.parse("1-3 b: cdefg"), Ok(entry(1, 3, 'b', "cdefg")));
+        assert_eq!(parser.parse("2-9 c: ccccccccc"), Ok(entry(2, 9, 'c', "ccccccccc")));
+        assert_eq!(parser.parse("12-19 d: goodjorb"), Ok(entry(12, 19, 'd', "goodjorb")));
+        assert_eq!(parser.parse("1 a: abc"), Err(String::from("can't parse `1 a: abc`")));
+    }
 
-        let res_one = parse_password_line(string_one);
-        let res_two = parse_password_line(string_two);
-        let res_three = parse_password_line(string_three);
-        let res_four = parse_password_line(string_four);
+    #[test]
+    fn test_sled_policy() {
+        init();
+        assert!(SledPolicy.is_valid(&entry(1, 3, 'a', "abcde")));
+        assert!(!SledPolicy.is_valid(&entry(1, 3, 'b', "cdefg")));
+        assert!(SledPolicy.is_valid(&entry(2, 9, 'c', "ccccccccc")));
+        assert_eq!(SledPolicy.check(&entry(1, 3, 'b', "cdefg")), Err(String::from("sled: 0 b, need 1-3")));
+    }
 
-        assert_eq!(res_one, (1, 3, 'a', String::from("abcde")));
-        assert_eq!(res_two, (1, 3, 'b', String::from("cdefg")));
-        assert_eq!(res_three, (2, 9, 'c', String::from("ccccccccc")));
-        assert_eq!(res_four, (12, 19, 'd', String::from("goodjorb")));
+    #[test]
+    fn test_toboggan_policy() {
+        init();
+        assert!(TobogganPolicy.is_valid(&entry(1, 3, 'a', "abcde")));
+        assert!(!TobogganPolicy.is_valid(&entry(1, 3, 'b', "cdefg")));
+        assert!(!TobogganPolicy.is_valid(&entry(2, 9, 'c', "ccccccccc")));
+        assert!(TobogganPolicy.is_valid(&entry(15, 16, 'o', "abcdefghijklmnop")));
+        assert!(TobogganPolicy.is_valid(&entry(11, 12, 'q', "qqqkqkqqqqzqqq")));
+        // Out of range positions don't panic
+        assert!(TobogganPolicy.is_valid(&entry(1, 40, 'a', "abc")));
+        assert!(!TobogganPolicy.is_valid(&entry(0, 40, 'a', "abc")));
+        assert_eq!(TobogganPolicy.check(&entry(2, 9, 'c', "ccccccccc")), Err(String::from("toboggan: c at both positions 2 and 9")));
+    }
+
+    #[test]
+    fn test_composed_policies() {
+        init();
+        let policy = build_policy("none", Some(6), Some("^[^x]*$"), Some("lower,digit")).unwrap();
+        assert!(policy.is_valid(&entry(1, 1, 'a', "abc123")));
+        assert_eq!(policy.check(&entry(1, 1, 'a', "abc12")), Err(String::from("min length: 5 characters, need 6")));
+        assert_eq!(policy.check(&entry(1, 1, 'a', "abcx123")), Err(String::from("regex: doesn't match `^[^x]*$`")));
+        assert_eq!(policy.check(&entry(1, 1, 'a', "ABCDEFG")), Err(String::from("classes: no lower, no digit")));
+
+        assert!(CharClasses(vec![CharClass::Upper, CharClass::Symbol]).is_valid(&entry(1, 1, 'a', "aB!")));
+        assert!(build_policy("sled", None, None, Some("lower,emoji")).is_err());
+        assert!(build_policy("bobsled", None, None, None).is_err());
+        assert!(build_policy("none", None, Some("("), None).is_err());
     }
 
     #[test]
-    fn test_is_valid_sled_password_tuple() {
+    fn test_violation_report() {
         init();
-        assert!(is_valid_sled_password_tuple((1, 3, 'a', String::from("abcde"))));
-        assert!(!is_valid_sled_password_tuple((1, 3, 'b', String::from("cdefg"))));
-        assert!(is_valid_sled_password_tuple((2, 9, 'c', String::from("ccccccccc"))));
+        let input: Vec<String> = vec!["1-3 a: abcde", "1-3 b: cdefg", "2-9 c: ccccccccc", "garbage"]
+            .into_iter().map(String::from).collect();
+        assert_eq!(violation_report(&input, &SledPolicy), vec![
+            "line 2: 1-3 b: cdefg -- sled: 0 b, need 1-3",
+            "line 4: garbage -- can't parse `garbage`",
+        ]);
+        let policy = build_policy("toboggan", Some(6), None, None).unwrap();
+        assert_eq!(violation_report(&input, &policy), vec![
+            "line 1: 1-3 a: abcde -- min length: 5 characters, need 6",
+            "line 2: 1-3 b: cdefg -- toboggan: b at neither position 1 nor 3",
+            "line 3: 2-9 c: ccccccccc -- toboggan: c at both positions 2 and 9",
+            "line 4: garbage -- can't parse `garbage`",
+        ]);
     }
 
     #[test]
-    fn test_is_valid_toboggan_password_tuple() {
+    fn test_count_valid() {
         init();
-        assert!(is_valid_toboggan_password_tuple((1, 3, 'a', String::from("abcde"))));
-        assert!(!is_valid_toboggan_password_tuple((1, 3, 'b', String::from("cdefg"))));
-        assert!(!is_valid_toboggan_password_tuple((2, 9, 'c', String::from("ccccccccc"))));
-        assert!(is_valid_toboggan_password_tuple((15, 16, 'o', String::from("abcdefghijklmnop"))));
-        assert!(is_valid_toboggan_password_tuple((11, 12, 'q', String::from("qqqkqkqqqqzqqq"))));
-    }    
-}
\ No newline at end of file
+        let mut input: Vec<String> = vec!["1-3 a: abcde", "1-3 b: cdefg", "2-9 c: ccccccccc"]
+            .into_iter().map(String::from).collect();
+        assert_eq!(count_valid(&input, &SledPolicy), Ok(2));
+        assert_eq!(problem_022(input.clone()), RetType::U32(1));
+
+        // A line that can't be read isn't quietly counted as invalid
+        input.insert(1, String::from("garbage"));
+        assert_eq!(count_valid(&input, &SledPolicy), Err(String::from("line 2: can't parse `garbage`")));
+        assert_eq!(problem_021(input), RetType::Failed);
+    }
+}
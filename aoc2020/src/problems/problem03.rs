@@ -1,3 +1,8 @@
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
 use log::{trace, debug, info, warn, error}; // trace, debug, info, warn, error
 use crate::util::RetType;
 
@@ -10,6 +15,51 @@ fn _get_rid_of_log_unused_import_warnings() {
     error!("Example error.");
 }
 
+/// Right so many squares for every so many down
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Slope {
+    pub right: usize,
+    pub down: usize,
+}
+
+/// The slopes part 2 checks
+pub const PART_2_SLOPES: [Slope; 5] = [
+    Slope { right: 1, down: 1 },
+    Slope { right: 3, down: 1 },
+    Slope { right: 5, down: 1 },
+    Slope { right: 7, down: 1 },
+    Slope { right: 1, down: 2 },
+];
+
+/// Parses "right,down", as in "Right 3, down 1" being 3,1
+impl FromStr for Slope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Slope, String> {
+        let mut parts = s.split(',').map(|x| x.trim().parse::<usize>());
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(Ok(right)), Some(Ok(down)), None) if down > 0 => Ok(Slope { right, down }),
+            _ => Err(format!("bad slope `{}`, expected right,down with down > 0", s)),
+        }
+    }
+}
+
+/// Where a slope goes on the way down.  Columns keep counting past the
+///  edge of the map rather than wrapping.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Path {
+    pub slope: Slope,
+    pub points: Vec<(usize, usize)>,
+    /// Whether each point is a tree
+    pub hits: Vec<bool>,
+}
+
+impl Path {
+    pub fn trees(&self) -> u128 {
+        self.hits.iter().filter(|&&hit| hit).count() as u128
+    }
+}
+
 /// ArboralLandscape
 /// Due to the local geology, trees in this area only grow on exact integer coordinates 
 ///  in a grid. You make a map (your puzzle input) of the open squares (.) and trees (#) 
@@ -23,7 +73,7 @@ pub struct ArboralLandscape {
 impl ArboralLandscape {
     pub fn new(string_map: Vec<String>) -> ArboralLandscape {
         let rows = string_map.len();
-        let cols = string_map[0].len();
+        let cols = string_map.first().map_or(0, |x| x.len());
 
         trace!("rows: {}", rows);
         trace!("cols: {}", cols);
@@ -31,7 +81,7 @@ impl ArboralLandscape {
         let mut tm = vec![vec![0; cols]; rows];
 
         trace!("tm rows: {}", tm.len());
-        trace!("tm cols: {}", tm[0].len());
+        trace!("tm cols: {}", cols);
 
         for row_num in 0..rows {
             for col_num in 0..cols {
@@ -45,30 +95,90 @@ impl ArboralLandscape {
         }
     }
 
-    #[allow(dead_code)]
-    pub fn print_n(&self, repeat_n: usize) {
-        for row in &self.tree_map {
-            for c in 0..row.len()*repeat_n {
-                match row[c%row.len()] {
-                    0u8 => {
-                        print!(".");
-                    },
-                    1u8 => {
-                        print!("#");
-                    },
-                    2u8 => {
-                        print!("O");
-                    },
-                    3u8 => {
-                        print!("X");
-                    },
-                    _ => {
-                        print!("?");
-                    }
+    /// Zero for an empty map
+    fn width(&self) -> usize {
+        self.tree_map.first().map_or(0, |row| row.len())
+    }
+
+    /// The map repeated repeat_n times across, as codes: 0 open, 1 tree,
+    ///  2 open square on a path, 3 tree on a path
+    fn grid(&self, repeat_n: usize, paths: &[Path]) -> Vec<Vec<u8>> {
+        let mut grid: Vec<Vec<u8>> = self.tree_map.iter()
+            .map(|row| (0..row.len() * repeat_n).map(|c| row[c % row.len()]).collect())
+            .collect();
+        for path in paths {
+            for &(row, col) in &path.points {
+                if col < grid[row].len() {
+                    grid[row][col] = if grid[row][col] % 2 == 1 { 3 } else { 2 };
                 }
             }
-            println!();
         }
+        grid
+    }
+
+    /// Copies of the map needed side by side to show all of the paths
+    pub fn repeats_needed(&self, paths: &[Path]) -> usize {
+        let widest = paths.iter().flat_map(|p| p.points.iter().map(|&(_, col)| col)).max().unwrap_or(0);
+        match self.width() {
+            0 => 1,
+            width => widest / width + 1,
+        }
+    }
+
+    /// Draw the map repeated repeat_n times with the paths on it: O where a
+    ///  path crosses open ground and X where it hits a tree
+    pub fn render(&self, repeat_n: usize, paths: &[Path]) -> String {
+        let mut out = String::new();
+        for row in self.grid(repeat_n, paths) {
+            for code in row {
+                out.push(match code {
+                    0u8 => '.',
+                    1u8 => '#',
+                    2u8 => 'O',
+                    3u8 => 'X',
+                    _ => '?',
+                });
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Save the same picture as render, as a binary PPM with each square
+    ///  drawn scale pixels wide
+    pub fn write_image(&self, filename: &str, repeat_n: usize, paths: &[Path], scale: usize) -> io::Result<()> {
+        self.write_image_to(&mut File::create(filename)?, repeat_n, paths, scale)
+    }
+
+    /// As write_image, to anything that can be written to
+    pub fn write_image_to(&self, out: &mut impl Write, repeat_n: usize, paths: &[Path], scale: usize) -> io::Result<()> {
+        let grid = self.grid(repeat_n, paths);
+        let (height, width) = (grid.len() * scale, grid.first().map_or(0, |row| row.len()) * scale);
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        for row in &grid {
+            let line: Vec<u8> = row.iter()
+                .flat_map(|&code| {
+                    let color: [u8; 3] = match code {
+                        0u8 => [0xFF, 0xFF, 0xFF],
+                        1u8 => [0x22, 0x8B, 0x22],
+                        2u8 => [0x1E, 0x90, 0xFF],
+                        _ => [0xDC, 0x14, 0x3C],
+                    };
+                    std::iter::repeat_n(color, scale).flatten()
+                })
+                .collect();
+            for _ in 0..scale {
+                pixels.extend_from_slice(&line);
+            }
+        }
+
+        write!(out, "P6\n{} {}\n255\n", width, height)?;
+        out.write_all(&pixels)
+    }
+
+    #[allow(dead_code)]
+    pub fn print_n(&self, repeat_n: usize) {
+        print!("{}", self.render(repeat_n, &[]));
     }
 
     #[allow(dead_code)]
@@ -76,15 +186,42 @@ impl ArboralLandscape {
         self.print_n(1);
     }
 
+    /// Follow a slope from the top left to the bottom.  An empty map has
+    ///  nowhere to go, so gives an empty path.
+    pub fn trace(&self, slope: Slope) -> Path {
+        let row_len = self.width();
+        if row_len == 0 {
+            return Path { slope, points: Vec::new(), hits: Vec::new() };
+        }
+        let points: Vec<(usize, usize)> = (0..self.tree_map.len())
+            .step_by(slope.down.max(1))
+            .enumerate()
+            .map(|(i, row)| (row, i * slope.right))
+            .collect();
+        let hits = points.iter().map(|&(row, col)| self.tree_map[row][col % row_len] == 1).collect();
+        Path { slope, points, hits }
+    }
+
     pub fn traverse(&mut self, down: u8, right: u8) -> u128 {
-        let mut tree_count: u128 = 0;
-        let mut position = (0, 0);
-        let row_len = self.tree_map[0].len();
-        while position.0 < self.tree_map.len() {
-            tree_count += self.tree_map[position.0][position.1 % row_len] as u128;
-            position = (position.0 + down as usize, position.1 + right as usize);
+        self.trace(Slope { right: right as usize, down: down as usize }).trees()
+    }
+
+    /// Try every slope in the ranges and return the fewest trees hit, with
+    ///  all the slopes that hit that few.  Downs of 0 are skipped.
+    pub fn fewest_trees(&self, rights: RangeInclusive<usize>, downs: RangeInclusive<usize>) -> Option<(u128, Vec<Slope>)> {
+        let mut best: Option<(u128, Vec<Slope>)> = None;
+        for down in downs.filter(|&d| d > 0) {
+            for right in rights.clone() {
+                let slope = Slope { right, down };
+                let trees = self.trace(slope).trees();
+                match best.as_mut() {
+                    Some((fewest, slopes)) if trees == *fewest => slopes.push(slope),
+                    Some((fewest, _)) if trees > *fewest => {},
+                    _ => best = Some((trees, vec![slope])),
+                }
+            }
         }
-        return tree_count;
+        best
     }
 }
 
@@ -113,19 +250,14 @@ pub fn problem_031(input: Vec<String>) -> RetType {
  *  on each of the listed slopes?
  */
 pub fn problem_032(input: Vec<String>) -> RetType {
-    let mut arboral_landscape = ArboralLandscape::new(input);
-    let slope_1_1 = arboral_landscape.traverse(1,1);
-    debug!("slope_1_1: {}", slope_1_1);
-    let slope_1_3 = arboral_landscape.traverse(1,3);
-    debug!("slope_1_3: {}", slope_1_3);
-    let slope_1_5 = arboral_landscape.traverse(1,5);
-    debug!("slope_1_5: {}", slope_1_5);
-    let slope_1_7 = arboral_landscape.traverse(1,7);
-    debug!("slope_1_7: {}", slope_1_7);
-    let slope_2_1 = arboral_landscape.traverse(2,1);
-    debug!("slope_2_1: {}", slope_2_1);
-    // arboral_landscape.print_n(5);
-    return RetType::U128(slope_1_1 * slope_1_3 * slope_1_5 * slope_1_7 * slope_2_1);
+    let arboral_landscape = ArboralLandscape::new(input);
+    let mut product: u128 = 1;
+    for slope in PART_2_SLOPES.iter() {
+        let trees = arboral_landscape.trace(*slope).trees();
+        debug!("slope {},{}: {}", slope.right, slope.down, trees);
+        product *= trees;
+    }
+    RetType::U128(product)
 }
 
 #[cfg(test)]
@@ -206,4 +338,103 @@ mod tests {
         let mut arboral_landscape = ArboralLandscape::new(string_map);
         assert_eq!(arboral_landscape.traverse(2,1), 1);
     }
-}
\ No newline at end of file
+
+    fn example() -> ArboralLandscape {
+        ArboralLandscape::new(vec![
+            "..##.......".to_string(),
+            "#...#...#..".to_string(),
+            ".#....#..#.".to_string(),
+            "..#.#...#.#".to_string(),
+            ".#...##..#.".to_string(),
+            "..#.##.....".to_string(),
+            ".#.#.#....#".to_string(),
+            ".#........#".to_string(),
+            "#.##...#...".to_string(),
+            "#...##....#".to_string(),
+            ".#..#...#.#".to_string()
+        ])
+    }
+
+    #[test]
+    fn test_trace_and_render() {
+        init();
+        let landscape = example();
+        let path = landscape.trace("3,1".parse().unwrap());
+        assert_eq!(path.trees(), 7);
+        assert_eq!(&path.points[..3], &[(0, 0), (1, 3), (2, 6)]);
+        assert_eq!(&path.hits[..3], &[false, false, true]);
+        assert_eq!(landscape.repeats_needed(std::slice::from_ref(&path)), 3);
+
+        // The drawing from the puzzle
+        let drawing = landscape.render(3, &[path]);
+        let lines: Vec<&str> = drawing.lines().collect();
+        assert_eq!(lines[0], "O.##.........##.........##.......");
+        assert_eq!(lines[1], "#..O#...#..#...#...#..#...#...#..");
+        assert_eq!(lines[2], ".#....X..#..#....#..#..#....#..#.");
+        assert_eq!(lines[10], ".#..#...#.#.#..#...#.#.#..#...X.#");
+        assert_eq!(drawing.matches('X').count(), 7);
+        assert_eq!(landscape.render(1, &[]).lines().next(), Some("..##......."));
+
+        let path = landscape.trace(Slope { right: 1, down: 2 });
+        assert_eq!(path.points.len(), 6);
+        assert_eq!(path.points[5], (10, 5));
+    }
+
+    #[test]
+    fn test_fewest_trees() {
+        init();
+        let landscape = example();
+        let product: u128 = PART_2_SLOPES.iter().map(|s| landscape.trace(*s).trees()).product();
+        assert_eq!(product, 336);
+
+        let (fewest, slopes) = landscape.fewest_trees(1..=7, 1..=1).unwrap();
+        assert_eq!(fewest, 1);
+        assert_eq!(slopes, vec![Slope { right: 2, down: 1 }]);
+        let (fewest, slopes) = landscape.fewest_trees(0..=1, 1..=2).unwrap();
+        assert_eq!(fewest, 1);
+        assert_eq!(slopes, vec![Slope { right: 0, down: 2 }]);
+        let (fewest, slopes) = landscape.fewest_trees(1..=1, 1..=2).unwrap();
+        assert_eq!(fewest, 2);
+        assert_eq!(slopes, vec![Slope { right: 1, down: 1 }, Slope { right: 1, down: 2 }]);
+        assert!(landscape.fewest_trees(1..=3, 0..=0).is_none());
+    }
+
+    #[test]
+    fn test_parse_slope() {
+        assert_eq!("3,1".parse::<Slope>(), Ok(Slope { right: 3, down: 1 }));
+        assert_eq!(" 1, 2".parse::<Slope>(), Ok(Slope { right: 1, down: 2 }));
+        assert!("1,0".parse::<Slope>().is_err());
+        assert!("1".parse::<Slope>().is_err());
+        assert!("1,2,3".parse::<Slope>().is_err());
+    }
+
+    #[test]
+    fn test_write_image() {
+        let landscape = example();
+        let path = landscape.trace(Slope { right: 3, down: 1 });
+        let mut bytes = Vec::new();
+        landscape.write_image_to(&mut bytes, 3, &[path], 2).unwrap();
+
+        let header = b"P6\n66 22\n255\n";
+        assert_eq!(&bytes[..header.len()], header);
+        assert_eq!(bytes.len(), header.len() + 66 * 22 * 3);
+        // Top left is on the path and open
+        assert_eq!(&bytes[header.len()..header.len() + 3], &[0x1E, 0x90, 0xFF]);
+    }
+
+    #[test]
+    fn test_empty_map() {
+        init();
+        for lines in [vec![], vec!["".to_string(), "".to_string()]] {
+            let landscape = ArboralLandscape::new(lines);
+            let path = landscape.trace(Slope { right: 3, down: 1 });
+            assert_eq!((path.trees(), path.points.len()), (0, 0));
+            assert_eq!(landscape.repeats_needed(&[path]), 1);
+            assert_eq!(landscape.fewest_trees(1..=2, 1..=1).map(|x| x.0), Some(0));
+
+            let mut bytes = Vec::new();
+            landscape.write_image_to(&mut bytes, 1, &[], 2).unwrap();
+            assert!(bytes.starts_with(b"P6\n0 "));
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use std::fmt;
+use std::fs;
 use regex::Regex;
 use log::{trace, debug, info, warn, error}; // trace, debug, info, warn, error
 use crate::util::RetType;
@@ -11,125 +13,210 @@ fn _get_rid_of_log_unused_import_warnings() {
     error!("Example error.");
 }
 
-///  Passport
-struct Passport {
-   byr: String, // Birth Year
-   iyr: String, // Issue year
-   eyr: String, // Expiration Year
-   hgt: String, // Height
-   hcl: String, // Hair Color
-   ecl: String, // Eye Color
-   pid: String, // Passport ID
-   cid: String, // Country ID
-   is_valid: bool
+/// The rules from the puzzle, in the schema file format:
+///  field, required or optional, then a validator and its arguments.
+/// byr (Birth Year) - four digits; at least 1920 and at most 2002.
+/// iyr (Issue Year) - four digits; at least 2010 and at most 2020.
+/// eyr (Expiration Year) - four digits; at least 2020 and at most 2030.
+/// hgt (Height) - a number followed by either cm or in:
+///     If cm, the number must be at least 150 and at most 193.
+///     If in, the number must be at least 59 and at most 76.
+/// hcl (Hair Color) - a # followed by exactly six characters 0-9 or a-f.
+/// ecl (Eye Color) - exactly one of: amb blu brn gry grn hzl oth.
+/// pid (Passport ID) - a nine-digit number, including leading zeroes.
+/// cid (Country ID) - ignored, missing or not.
+const STANDARD_SCHEMA: &str = "\
+# field required validator
+byr required year 1920 2002
+iyr required year 2010 2020
+eyr required year 2020 2030
+hgt required units cm 150 193 in 59 76
+hcl required regex ^#[0-9a-f]{6}$
+ecl required oneof amb blu brn gry grn hzl oth
+pid required regex ^[0-9]{9}$
+cid optional any
+";
+
+/// How a field's value is checked
+#[derive(Clone, Debug)]
+pub enum Validator {
+    Any,
+    /// Four digits, within the bounds
+    Year(u32, u32),
+    /// A number followed by one of the units, within that unit's bounds
+    Units(Vec<(String, u32, u32)>),
+    Regex(Regex),
+    OneOf(Vec<String>),
 }
 
-impl Passport {
-    fn new(string_map: Vec<String>, validate: bool) -> Passport {
-        let mut passport = Passport {
-           byr: "".to_string(),
-           iyr: "".to_string(),
-           eyr: "".to_string(),
-           hgt: "".to_string(),
-           hcl: "".to_string(),
-           ecl: "".to_string(),
-           pid: "".to_string(),
-           cid: "".to_string(),
-           is_valid: false,
-        };
+impl Validator {
+    fn parse(kind: &str, args: &[&str]) -> Result<Validator, String> {
+        let number = |x: &str| x.parse::<u32>().map_err(|_| format!("bad number `{}`", x));
+        match (kind, args.len()) {
+            ("any", 0) => Ok(Validator::Any),
+            ("year", 2) => Ok(Validator::Year(number(args[0])?, number(args[1])?)),
+            ("units", n) if n > 0 && n % 3 == 0 => args.chunks(3)
+                .map(|x| Ok((x[0].to_string(), number(x[1])?, number(x[2])?)))
+                .collect::<Result<Vec<_>, String>>()
+                .map(Validator::Units),
+            ("regex", n) if n > 0 => Regex::new(&args.join(" ")).map(Validator::Regex).map_err(|e| e.to_string()),
+            ("oneof", n) if n > 0 => Ok(Validator::OneOf(args.iter().map(|x| x.to_string()).collect())),
+            ("any", _) | ("year", _) | ("units", _) | ("regex", _) | ("oneof", _) => Err(format!("wrong arguments for `{}`", kind)),
+            _ => Err(format!("unknown validator `{}`", kind)),
+        }
+    }
 
-        let mut field_count = 0;
-        for line in string_map {
-            let re = Regex::new(r"([a-z]{3}):(#?[a-z,0-9]*)\s?").unwrap();
-            let cap = re.find_iter(&line);
-
-            for entry in cap {
-                let parsed_entry: Vec<&str> = entry.as_str().trim().split(":").collect();
-                trace!("{},{}", parsed_entry[0], parsed_entry[1]);
-                field_count += 1;
-                match parsed_entry[0] {
-                    "byr" => passport.byr = parsed_entry[1].to_string(),
-                    "iyr" => passport.iyr = parsed_entry[1].to_string(),
-                    "eyr" => passport.eyr = parsed_entry[1].to_string(),
-                    "hgt" => passport.hgt = parsed_entry[1].to_string(),
-                    "hcl" => passport.hcl = parsed_entry[1].to_string(),
-                    "ecl" => passport.ecl = parsed_entry[1].to_string(),
-                    "pid" => passport.pid = parsed_entry[1].to_string(),
-                    "cid" => passport.cid = parsed_entry[1].to_string(),
-                    _ => {}
-                }
+    /// Ok, or what is wrong with the value
+    fn check(&self, value: &str) -> Result<(), String> {
+        let bounds = |x: u32, min: u32, max: u32, shown: &str| {
+            if x < min {
+                Err(format!("{} below min {}", shown, min))
+            } else if x > max {
+                Err(format!("{} above max {}", shown, max))
+            } else {
+                Ok(())
             }
+        };
+        match self {
+            Validator::Any => Ok(()),
+            Validator::Year(min, max) => {
+                if value.len() != 4 || !value.chars().all(|c| c.is_ascii_digit()) {
+                    return Err(format!("`{}` is not a four digit year", value));
+                }
+                bounds(value.parse().unwrap(), *min, *max, value)
+            },
+            Validator::Units(units) => {
+                let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+                let (digits, unit) = value.split_at(split);
+                let names: Vec<&str> = units.iter().map(|u| u.0.as_str()).collect();
+                let number = digits.parse::<u32>().map_err(|_| format!("`{}` doesn't start with a number", value))?;
+                match units.iter().find(|u| u.0 == unit) {
+                    Some((_, min, max)) => bounds(number, *min, *max, value),
+                    None if unit.is_empty() => Err(format!("`{}` needs a unit: {}", value, names.join(" or "))),
+                    None => Err(format!("`{}` unit {} isn't {}", value, unit, names.join(" or "))),
+                }
+            },
+            Validator::Regex(re) => {
+                if re.is_match(value) {
+                    return Ok(());
+                }
+                Err(format!("`{}` doesn't match {}", value, re.as_str()))
+            },
+            Validator::OneOf(options) => {
+                if options.iter().any(|x| x == value) {
+                    return Ok(());
+                }
+                Err(format!("`{}` isn't one of {}", value, options.join(" ")))
+            },
         }
+    }
+}
 
-        if field_count == 8 {
-            passport.is_valid = true;
-        } else if field_count == 7 && passport.cid == "" {
-            passport.is_valid = true;
-        }
+#[derive(Clone, Debug)]
+pub struct FieldRule {
+    pub field: String,
+    pub required: bool,
+    pub validator: Validator,
+}
 
-        if validate {
-            passport.validate();
-        }
+/// Why a passport was turned away
+#[derive(Clone, Debug, PartialEq)]
+pub struct Failure {
+    pub field: String,
+    pub problem: String,
+}
 
-        return passport;
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.problem)
     }
+}
 
-    /// byr (Birth Year) - four digits; at least 1920 and at most 2002.
-    /// iyr (Issue Year) - four digits; at least 2010 and at most 2020.
-    /// eyr (Expiration Year) - four digits; at least 2020 and at most 2030.
-    /// hgt (Height) - a number followed by either cm or in:
-    ///     If cm, the number must be at least 150 and at most 193.
-    ///     If in, the number must be at least 59 and at most 76.
-    /// hcl (Hair Color) - a # followed by exactly six characters 0-9 or a-f.
-    /// ecl (Eye Color) - exactly one of: amb blu brn gry grn hzl oth.
-    /// pid (Passport ID) - a nine-digit number, including leading zeroes.
-    /// cid (Country ID) - ignored, missing or not.
-    fn validate(&mut self) {
-        // Birth year
-        if self.byr.len() != 4 || self.byr.parse::<u32>().unwrap() < 1920 || self.byr.parse::<u32>().unwrap() > 2002 {
-            self.is_valid = false;
-        }
-        // Issue year
-        if self.iyr.len() != 4 || self.iyr.parse::<u32>().unwrap() < 2010 || self.iyr.parse::<u32>().unwrap() > 2020 {
-            self.is_valid = false;
-        }
-        // Exp. Year
-        if self.eyr.len() != 4 || self.eyr.parse::<u32>().unwrap() < 2010 || self.eyr.parse::<u32>().unwrap() > 2030 {
-            self.is_valid = false;
-        }
-        // Height
-        let he_re = Regex::new(r"(\d+)(cm|in)").unwrap();
-        if !he_re.is_match(&self.hgt) {
-            self.is_valid = false;
-        } else {
-            let cap = he_re.captures(&self.hgt).unwrap();
-            if &cap[2] == "cm"  {
-                if cap[1].parse::<u32>().unwrap() < 150 || cap[1].parse::<u32>().unwrap() > 193 {
-                    self.is_valid = false;
-                }
-            } else if &cap[2] == "in" {
-                if cap[1].parse::<u32>().unwrap() < 59 || cap[1].parse::<u32>().unwrap() > 76 {
-                    self.is_valid = false;
-                }       
-            } else {
-                self.is_valid = false;
+/// The fields a passport has, and what goes in them
+#[derive(Clone, Debug)]
+pub struct Schema {
+    pub rules: Vec<FieldRule>,
+}
+
+impl Schema {
+    /// The puzzle's own rules
+    pub fn standard() -> Schema {
+        let lines: Vec<String> = STANDARD_SCHEMA.lines().map(|x| x.to_string()).collect();
+        Schema::parse(&lines).unwrap()
+    }
+
+    /// One rule per line: field, required or optional, validator, and the
+    ///  validator's arguments.  Blank lines and lines starting with # are
+    ///  skipped.
+    pub fn parse(lines: &[String]) -> Result<Schema, String> {
+        let mut rules = Vec::new();
+        for (i, line) in lines.iter().enumerate() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() || words[0].starts_with('#') {
+                continue;
             }
+            let fail = |e: String| format!("line {}: {}", i + 1, e);
+            if words.len() < 3 {
+                return Err(fail(String::from("expected field, required or optional, and a validator")));
+            }
+            let required = match words[1] {
+                "required" => true,
+                "optional" => false,
+                x => return Err(fail(format!("expected required or optional, not `{}`", x))),
+            };
+            let validator = Validator::parse(words[2], &words[3..]).map_err(fail)?;
+            rules.push(FieldRule { field: words[0].to_string(), required, validator });
         }
-        // Hair Color
-        let hc_re = Regex::new(r"#[0-9a-f]{6}").unwrap();
-        if !hc_re.is_match(&self.hcl) || self.hcl.len() != 7 {
-            self.is_valid = false;
-        }
-        // Eye Color
-        match &self.ecl[..] {
-            "amb" | "blu" | "brn" | "gry" | "grn" | "hzl" | "oth" => {},
-            _ => self.is_valid = false
+        Ok(Schema { rules })
+    }
+
+    pub fn load(filename: &str) -> Result<Schema, String> {
+        let text = fs::read_to_string(filename).map_err(|e| format!("{}: {}", filename, e))?;
+        Schema::parse(&text.lines().map(|x| x.to_string()).collect::<Vec<_>>())
+    }
+
+    /// Everything wrong with the passport, in schema order.  Without
+    ///  check_values only missing fields count.
+    pub fn check(&self, passport: &Passport, check_values: bool) -> Vec<Failure> {
+        let mut failures = Vec::new();
+        for rule in &self.rules {
+            let problem = match passport.get(&rule.field) {
+                None if rule.required => Some(String::from("missing")),
+                None => None,
+                Some(value) if check_values => rule.validator.check(value).err(),
+                Some(_) => None,
+            };
+            if let Some(problem) = problem {
+                failures.push(Failure { field: rule.field.clone(), problem });
+            }
         }
-        // PID
-        let pid_re = Regex::new(r"\d{9}").unwrap();
-        if !pid_re.is_match(&self.pid) || self.pid.len() != 9 {
-            self.is_valid = false;
+        failures
+    }
+}
+
+///  Passport
+pub struct Passport {
+    /// Fields in the order they were written
+    fields: Vec<(String, String)>,
+    /// First line of the passport in the batch file, counting from 1
+    pub line: usize,
+}
+
+impl Passport {
+    fn new(string_map: Vec<String>, line: usize) -> Passport {
+        let mut fields = Vec::new();
+        for entry in string_map.iter().flat_map(|x| x.split_whitespace()) {
+            let mut parts = entry.splitn(2, ':');
+            let key = parts.next().unwrap();
+            let value = parts.next().unwrap_or("");
+            trace!("{},{}", key, value);
+            fields.push((key.to_string(), value.to_string()));
         }
+        Passport { fields, line }
+    }
+
+    pub fn get(&self, field: &str) -> Option<&str> {
+        self.fields.iter().find(|(key, _)| key == field).map(|(_, value)| value.as_str())
     }
 }
 
@@ -138,31 +225,31 @@ impl Passport {
 ///  put it over there.  Nothing shady, just a business man trying--
 ///  I mean, a government official--trying to make his way in this
 ///  world.
-fn semi_questionable_passport_factory(string_map: Vec<String>, validate: bool) -> Vec<Passport> {
+pub fn semi_questionable_passport_factory(string_map: Vec<String>) -> Vec<Passport> {
     let mut temp_vec: Vec<String> = Vec::new();
     let mut passport_vec: Vec<Passport> = Vec::new();
+    let mut start = 1;
 
-    for line in string_map {
-        if line.len() == 0 {
-            passport_vec.push(Passport::new(temp_vec.clone(), validate));
+    for (i, line) in string_map.into_iter().enumerate() {
+        if line.trim().is_empty() {
+            if !temp_vec.is_empty() {
+                passport_vec.push(Passport::new(temp_vec.clone(), start));
+            }
             temp_vec.clear();
+            start = i + 2;
         } else {
             temp_vec.push(line);
         }
     }
-    passport_vec.push(Passport::new(temp_vec.clone(), validate));
+    if !temp_vec.is_empty() {
+        passport_vec.push(Passport::new(temp_vec, start));
+    }
 
     return passport_vec;
 }
 
-fn count_valid_passports(passports: Vec<Passport>) -> u32 {
-    let mut count = 0;
-    for passport in passports {
-        if passport.is_valid {
-            count += 1;
-        }
-    }
-    return count;
+fn count_valid_passports(passports: &[Passport], schema: &Schema, check_values: bool) -> u32 {
+    passports.iter().filter(|p| schema.check(p, check_values).is_empty()).count() as u32
 }
 
 /**
@@ -181,7 +268,7 @@ fn count_valid_passports(passports: Vec<Passport>) -> u32 {
  *  detecting which passports have all required fields.
  */
 pub fn problem_041(input: Vec<String>) -> RetType {
-    return RetType::U32(count_valid_passports(semi_questionable_passport_factory(input, false)));
+    RetType::U32(count_valid_passports(&semi_questionable_passport_factory(input), &Schema::standard(), false))
 }
 
 /**
@@ -191,7 +278,7 @@ pub fn problem_041(input: Vec<String>) -> RetType {
  *  through. Better add some data validation, quick!
  */
 pub fn problem_042(input: Vec<String>) -> RetType {
-    return RetType::U32(count_valid_passports(semi_questionable_passport_factory(input, true)));
+    RetType::U32(count_valid_passports(&semi_questionable_passport_factory(input), &Schema::standard(), true))
 }
 
 #[cfg(test)]
@@ -217,8 +304,10 @@ mod tests {
             "byr:1937 iyr:2017 cid:147 hgt:183cm".to_string()
         ];
 
-        let passport = Passport::new(passport_string, false);
-        assert!(passport.is_valid);
+        let schema = Schema::standard();
+        let passport = Passport::new(passport_string, 1);
+        assert!(schema.check(&passport, false).is_empty());
+        assert_eq!(passport.get("hcl"), Some("#fffffd"));
 
         trace!("Second entry");
 
@@ -226,8 +315,8 @@ mod tests {
             "iyr:2013 ecl:amb cid:350 eyr:2023 pid:028048884".to_string(),
             "hcl:#cfa07d byr:1929".to_string()
         ];
-        let passport2 = Passport::new(passport_string2, false);
-        assert!(!passport2.is_valid);
+        let passport2 = Passport::new(passport_string2, 1);
+        assert_eq!(schema.check(&passport2, false), vec![Failure { field: String::from("hgt"), problem: String::from("missing") }]);
     }
 
     #[test]
@@ -260,12 +349,14 @@ mod tests {
             "iyr:2011 ecl:brn hgt:59in".to_string(),
         ];
 
-        let passports = semi_questionable_passport_factory(passports_strings, false);
-        assert!(passports[0].is_valid);
-        assert!(!passports[1].is_valid);
-        assert!(passports[2].is_valid);
-        assert!(!passports[3].is_valid);
-        assert_eq!(count_valid_passports(passports), 2);
+        let schema = Schema::standard();
+        let passports = semi_questionable_passport_factory(passports_strings);
+        assert!(schema.check(&passports[0], false).is_empty());
+        assert!(!schema.check(&passports[1], false).is_empty());
+        assert!(schema.check(&passports[2], false).is_empty());
+        assert!(!schema.check(&passports[3], false).is_empty());
+        assert_eq!(passports.iter().map(|p| p.line).collect::<Vec<_>>(), vec![1, 4, 7, 12]);
+        assert_eq!(count_valid_passports(&passports, &schema, false), 2);
     }
 
     #[test]
@@ -287,8 +378,9 @@ mod tests {
             "pid:3556412378 byr:2007".to_string(),
         ];
 
-        let invalid_passports = semi_questionable_passport_factory(invalid_passport_strings, true);
-        assert_eq!(count_valid_passports(invalid_passports), 0);
+        let schema = Schema::standard();
+        let invalid_passports = semi_questionable_passport_factory(invalid_passport_strings);
+        assert_eq!(count_valid_passports(&invalid_passports, &schema, true), 0);
 
         let valid_passport_strings = vec![
             "pid:087499704 hgt:74in ecl:grn iyr:2012 eyr:2030 byr:1980".to_string(),
@@ -305,7 +397,61 @@ mod tests {
             "iyr:2010 hgt:158cm hcl:#b6652a ecl:blu byr:1944 eyr:2021 pid:093154719".to_string(),
         ];
 
-        let valid_passports = semi_questionable_passport_factory(valid_passport_strings, true);
-        assert_eq!(count_valid_passports(valid_passports), 4);
-    }    
+        let valid_passports = semi_questionable_passport_factory(valid_passport_strings);
+        assert_eq!(count_valid_passports(&valid_passports, &schema, true), 4);
+    }
+
+    fn failures(line: &str) -> Vec<String> {
+        let passport = Passport::new(vec![line.to_string()], 1);
+        Schema::standard().check(&passport, true).iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn test_passport_failures() {
+        init();
+        let valid = "pid:087499704 hgt:74in ecl:grn iyr:2012 eyr:2030 byr:1980 hcl:#623a2f";
+        assert!(failures(valid).is_empty());
+        assert_eq!(failures(&valid.replace("74in", "200cm")), vec!["hgt: 200cm above max 193"]);
+        assert_eq!(failures(&valid.replace("74in", "58in")), vec!["hgt: 58in below min 59"]);
+        assert_eq!(failures(&valid.replace("74in", "170")), vec!["hgt: `170` needs a unit: cm or in"]);
+        assert_eq!(failures(&valid.replace("74in", "170mm")), vec!["hgt: `170mm` unit mm isn't cm or in"]);
+        assert_eq!(failures(&valid.replace("74in", "cm")), vec!["hgt: `cm` doesn't start with a number"]);
+        // Years that aren't numbers don't panic
+        assert_eq!(failures(&valid.replace("byr:1980", "byr:19x0")), vec!["byr: `19x0` is not a four digit year"]);
+        // Expiry is 2020 at the earliest
+        assert_eq!(failures(&valid.replace("eyr:2030", "eyr:2015")), vec!["eyr: 2015 below min 2020"]);
+        assert_eq!(failures(&valid.replace("ecl:grn", "ecl:zzz")), vec!["ecl: `zzz` isn't one of amb blu brn gry grn hzl oth"]);
+        assert_eq!(failures(&valid.replace("pid:087499704", "pid:0874997041")), vec!["pid: `0874997041` doesn't match ^[0-9]{9}$"]);
+        assert_eq!(failures("hcl:#123abz cid:1"), vec![
+            "byr: missing", "iyr: missing", "eyr: missing", "hgt: missing",
+            "hcl: `#123abz` doesn't match ^#[0-9a-f]{6}$", "ecl: missing", "pid: missing",
+        ]);
+    }
+
+    #[test]
+    fn test_schema_file() {
+        init();
+        let lines: Vec<String> = vec!["# tiny", "", "age required units y 18 99", "name optional regex ^[A-Z][a-z]+$"]
+            .into_iter().map(String::from).collect();
+        let schema = Schema::parse(&lines).unwrap();
+        assert_eq!(schema.rules.len(), 2);
+        assert!(!schema.rules[1].required);
+        let passport = Passport::new(vec!["age:17y name:bob".to_string()], 1);
+        let found: Vec<String> = schema.check(&passport, true).iter().map(|x| x.to_string()).collect();
+        assert_eq!(found, vec!["age: 17y below min 18", "name: `bob` doesn't match ^[A-Z][a-z]+$"]);
+
+        let bad = |line: &str| Schema::parse(&[line.to_string()]).unwrap_err();
+        assert_eq!(bad("byr required"), "line 1: expected field, required or optional, and a validator");
+        assert_eq!(bad("byr maybe any"), "line 1: expected required or optional, not `maybe`");
+        assert_eq!(bad("byr required year 1920"), "line 1: wrong arguments for `year`");
+        assert_eq!(bad("byr required year 1920 x"), "line 1: bad number `x`");
+        assert_eq!(bad("byr required date"), "line 1: unknown validator `date`");
+
+        let filename = std::env::temp_dir().join("aoc2020_problem04_schema.txt");
+        let filename = filename.to_str().unwrap();
+        fs::write(filename, STANDARD_SCHEMA).unwrap();
+        assert_eq!(Schema::load(filename).unwrap().rules.len(), 8);
+        fs::remove_file(filename).unwrap();
+        assert!(Schema::load("/no/such/schema").is_err());
+    }
 }
\ No newline at end of file
@@ -1,6 +1,8 @@
+use std::convert::TryFrom;
 use regex::Regex;
 use log::{trace, debug, info, warn, error}; // trace, debug, info, warn, error
 use crate::matrix;
+use crate::graph;
 use crate::util::RetType;
 
 #[allow(dead_code)]
@@ -48,91 +50,59 @@ fn parse_luggage_line(line: String) -> (String, Vec<(u32, String)>) {
     return (container.to_string(), contents);
 }
 
-// These should probably be spun out into its own directed graph
 fn add_node_and_edges(g: &mut matrix::SquareMatrix<u32>, n: String, v: Vec<(u32, String)>) {
     for edge in v {
         g.set_in_labelled_row(n.clone(), edge.1, edge.0);
     }
 }
 
-fn contains(g: &mut matrix::SquareMatrix<u32>, n: String, m: String) -> bool {
-    let sg_index = g.index_of(m.clone());
-
-    match sg_index {
-        Some(sg) => {
-            let mut res = false;
-
-            let container = g.get_labelled_row(n.clone());
-
-            // trace!("Container: {:?}", container);
-            // trace!("labels: {:?}", g.get_labels());
-            // trace!("g: {:?}", g.get_values());
-            // trace!("n: {:?}", n.clone());
-            // trace!("m: {:?}", m.clone());
-
-            if container[sg] > 0 {
-                return true;
-            } else {
-                for i in 0..container.len() {
-                    if container[i] > 0 {
-                        res |= contains(g, g.get_label(i), m.clone());
-                    }
-                }
-            }
-
-            res
-        },
-        None => false
+fn build_graph(input: Vec<String>) -> graph::LabelledDigraph {
+    let mut g = matrix::SquareMatrix::with_storage(0, matrix::Storage::Sparse);
+    for line in input.into_iter() {
+        let (bag, contents) = parse_luggage_line(line);
+        add_node_and_edges(&mut g, bag, contents);
     }
-}
 
-fn forward_count(g: &mut matrix::SquareMatrix<u32>, n: String) -> u32 {
-        let mut count = 0;
-        let container = g.get_labelled_row(n.clone());
+    trace!("Bag graph:\n{}", g.to_dot(&[]));
 
-        trace!("n = {}, {:?}", n.clone(), container);
+    graph::LabelledDigraph::from_matrix(&g)
+}
 
-        for i in 0..container.len() {
-            if container[i] != 0 {
-                count += container[i] + container[i]*forward_count(g, g.get_labels()[i].clone());
-            }
-        }
+#[allow(dead_code)]
+fn contains(g: &graph::LabelledDigraph, n: &str, m: &str) -> bool {
+    match (g.index_of(n), g.index_of(m)) {
+        (Some(x), Some(y)) => g.reaches(x, y),
+        _ => false
+    }
+}
 
-        count
+fn forward_count(g: &graph::LabelledDigraph, n: &str) -> Result<u32, String> {
+    let count = g.weighted_descendant_count(n)?;
+    u32::try_from(count).map_err(|_| format!("{} bags don't fit in a u32", count))
 }
 
 /// Problem #07, part 1
 /// How many bag colors can eventually contain at least one shiny gold bag?
 pub fn problem_071(input: Vec<String>) -> RetType {
-    let mut count: u32 = 0;
-    let mut g = matrix::SquareMatrix::new(0);
-    for line in input.into_iter() {
-        let (bag, contents) = parse_luggage_line(line);
-        add_node_and_edges(&mut g, bag, contents);
-    }
-
-    trace!("Labels: {:?}", g.get_labels());
-    trace!("Values: {:?}", g.get_values());
+    let g = build_graph(input);
 
-    for bag_type in g.get_labels() {
-        if contains(&mut g, bag_type, "shiny gold".to_string()) {
-            count += 1;
-        }
-    }
+    let count = g.ancestors_of("shiny gold").map_or(0, |x| x.len());
 
-    return RetType::U32(count);
+    RetType::U32(count as u32)
 }
 
 /// Problem #07, part 2
-/// How many bag colors can eventually contain at least one shiny gold bag?
+/// How many individual bags are required inside your single shiny gold bag?
 pub fn problem_072(input: Vec<String>) -> RetType {
-    let mut g = matrix::SquareMatrix::new(0);
-    for line in input.into_iter() {
-        let (bag, contents) = parse_luggage_line(line);
-        add_node_and_edges(&mut g, bag, contents);
-    }
+    let g = build_graph(input);
 
-    RetType::U32(forward_count(&mut g, "shiny gold".to_string()))
+    match forward_count(&g, "shiny gold") {
+        Ok(count) => RetType::U32(count),
+        Err(e) => {
+            error!("Couldn't count the bags: {}", e);
+            RetType::Failed
+        }
+    }
 }
 
 #[cfg(test)]
@@ -179,12 +149,12 @@ mod tests {
         add_node_and_edges(&mut g, "light red".to_string(), vec![(1, "bright white".to_string()), (2, "muted yellow".to_string())]);
 
         assert_eq!(g.size(), 3);
-        assert_eq!(g.get_labelled_row("light red".to_string()), vec![0, 1, 2]);
+        assert_eq!(g.get_labelled_row("light red").as_deref(), Some(&[0, 1, 2][..]));
 
         add_node_and_edges(&mut g, "bright white".to_string(), vec![(1, "shiny gold".to_string())]);
 
         assert_eq!(g.size(), 4);
-        assert_eq!(g.get_labelled_row("bright white".to_string()), vec![0, 0, 0, 1]);
+        assert_eq!(g.get_labelled_row("bright white").as_deref(), Some(&[0, 0, 0, 1][..]));
     }
 
     #[test]
@@ -196,11 +166,13 @@ mod tests {
         add_node_and_edges(&mut g, "light red".to_string(), vec![(1, "bright white".to_string()), (2, "muted yellow".to_string())]);
 
         assert_eq!(g.size(), 3);
-        assert_eq!(g.get_labelled_row("light red".to_string()), vec![0, 1, 2]);
+        assert_eq!(g.get_labelled_row("light red").as_deref(), Some(&[0, 1, 2][..]));
 
         add_node_and_edges(&mut g, "bright white".to_string(), vec![(1, "shiny gold".to_string())]);
 
-        assert!(contains(&mut g, "light red".to_string(), "shiny gold".to_string()));
+        let g = graph::LabelledDigraph::from_matrix(&g);
+        assert!(contains(&g, "light red", "shiny gold"));
+        assert!(!contains(&g, "shiny gold", "light red"));
     }
 
     #[test]
@@ -237,19 +209,12 @@ mod tests {
             "dark blue bags contain 2 dark violet bags.".to_string(),
             "dark violet bags contain no other bags.".to_string(),
         ];
-    
-        let mut g = matrix::SquareMatrix::new(0);
-        for line in input.into_iter() {
-            let (bag, contents) = parse_luggage_line(line);
-            add_node_and_edges(&mut g, bag, contents);
-        }
-
-        trace!("Labels: {:?}", g.get_labels());
-        trace!("Values: {:?}", g.get_values());
 
+        let g = build_graph(input);
 
-        let res = forward_count(&mut g, "shiny gold".to_string());
+        let res = forward_count(&g, "shiny gold");
 
-        assert_eq!(res, 126);
+        assert_eq!(res, Ok(126));
+        assert!(forward_count(&g, "plaid").is_err());
     }
 }
\ No newline at end of file
@@ -15,7 +15,13 @@ fn _get_rid_of_log_unused_import_warnings() {
 /// Run your copy of the boot code. Immediately before any instruction is 
 ///  executed a second time, what value is in the accumulator?
 pub fn problem_081(input: Vec<String>) -> RetType {
-    let mut vm = virtualmachine::Vm::new(input);
+    let mut vm = match virtualmachine::Vm::from_source(input) {
+        Ok(vm) => vm,
+        Err(e) => {
+            error!("Couldn't load the boot code: {}", e);
+            return RetType::Failed;
+        }
+    };
     vm.execute_until_repeat();
     RetType::I32(vm.get_acc())
 }
@@ -25,7 +31,37 @@ pub fn problem_081(input: Vec<String>) -> RetType {
 ///  jmp (to nop) or nop (to jmp). What is the value of the accumulator after 
 ///  the program terminates?
 pub fn problem_082(input: Vec<String>) -> RetType {
-    let mut vm = virtualmachine::Vm::new(input);
+    let mut vm = match virtualmachine::Vm::from_source(input) {
+        Ok(vm) => vm,
+        Err(e) => {
+            error!("Couldn't load the boot code: {}", e);
+            return RetType::Failed;
+        }
+    };
     vm.execute_gamegirl();
     RetType::I32(vm.get_acc())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init() {
+        if env_logger::try_init().is_ok() {
+            info!("Initializing logging...");
+        }
+    }
+
+    #[test]
+    fn test_problem_08() {
+        init();
+        let input: Vec<String> = virtualmachine::DAY8_EXAMPLE.iter().map(|x| x.to_string()).collect();
+        assert_eq!(problem_081(input.clone()), RetType::I32(5));
+        assert_eq!(problem_082(input), RetType::I32(8));
+
+        // A line the VM can't read is reported, not a panic
+        let input = vec!["nop +0".to_string(), "jpm -1".to_string()];
+        assert_eq!(problem_081(input.clone()), RetType::Failed);
+        assert_eq!(problem_082(input), RetType::Failed);
+    }
+}
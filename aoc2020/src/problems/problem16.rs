@@ -2,6 +2,8 @@ use log::{trace, debug, info, warn, error}; // trace, debug, info, warn, error
 use std::collections::HashMap;
 use regex::Regex;
 use crate::util::RetType;
+use crate::assignment;
+use crate::assignment::Assignment;
 
 #[allow(dead_code)]
 fn _get_rid_of_log_unused_import_warnings() {
@@ -98,7 +100,7 @@ fn in_range(value: u32, ranges: Vec<(u32, u32)>) -> bool {
     return true;
 }
 
-fn vote_system(tickets: &Vec<Ticket>, rules: &HashMap<String, Vec<(u32, u32)>>) -> HashMap<String, usize> {
+fn vote_system(tickets: &Vec<Ticket>, rules: &HashMap<String, Vec<(u32, u32)>>) -> Result<HashMap<String, usize>, &'static str> {
     let mut rules_keys: Vec<String> = Vec::new();
     let mut rules_values: Vec<Vec<(u32, u32)>> = Vec::new();
 
@@ -107,58 +109,30 @@ fn vote_system(tickets: &Vec<Ticket>, rules: &HashMap<String, Vec<(u32, u32)>>)
         rules_values.push(val.clone());
     }
 
-    let mut table = vec![vec![1; rules.len()]; rules.len()];
+    let mut table = vec![vec![true; rules.len()]; rules.len()];
 
     for i in 0..rules.len() {
         for ticket in tickets {
             for j in 0..ticket.unassigned_fields.len() {
                 if !in_range(ticket.unassigned_fields[j], rules_values[i].clone()) {
-                    table[i][j] = 0;
+                    table[i][j] = false;
                 }
             }
         }
     }
 
-    reduce(&mut table);
     debug!("Table: {:?}", table);
 
-    let mut res: HashMap<String, usize> = HashMap::new();
-    for i in 0..table.len() {
-        let index = table[i].iter().position(|&x| x == 1).unwrap();
-        res.insert(rules_keys[i].clone(), index);
-    }
-
-    res
-}
-
-fn reduce(m: &mut Vec<Vec<u32>>) {
-    let mut current: u32 = 1;
-    let mut history: Vec<u32> = Vec::new();
-
-    'searcher:  loop {
-        if current > m.len() as u32 {
-            break 'searcher;
-        }
-        for i in 0..m.len() {
-            let mut s = 0;
-            let mut idx = 0;
-            for j in 0..m[i].len() {
-                s += m[i][j];
-                if m[i][j] == 1 && !history.contains(&(j as u32)) {
-                    idx = j;
-                }
-            }
-            if s == current {
-                history.push(idx as u32);
-                for j in 0..m[i].len() {
-                    if j != idx && m[i][j] == 1 {
-                        m[i][j] = 0;
-                    }
-                }
-                current += 1;
-                continue 'searcher;
+    match assignment::solve(&table) {
+        Assignment::Unique(columns) => {
+            let mut res: HashMap<String, usize> = HashMap::new();
+            for (i, index) in columns.into_iter().enumerate() {
+                res.insert(rules_keys[i].clone(), index);
             }
-        }
+            Ok(res)
+        },
+        Assignment::Multiple(_) => Err("More than one field assignment fits the tickets."),
+        Assignment::Impossible => Err("No field assignment fits the tickets."),
     }
 }
 
@@ -186,7 +160,13 @@ pub fn problem_162(input: Vec<String>) -> RetType {
         .collect()
     ;
 
-    let res = vote_system(&valid_tickets, &rules);
+    let res = match vote_system(&valid_tickets, &rules) {
+        Ok(res) => res,
+        Err(e) => {
+            error!("Couldn't assign ticket fields: {}", e);
+            return RetType::Failed;
+        }
+    };
 
     let mut ans: u128 = 1;
     for (key, _val) in rules.iter() {
@@ -282,21 +262,33 @@ mod tests {
             .collect()
         ;
 
-        let res = vote_system(&valid_tickets, &rules);
+        let res = vote_system(&valid_tickets, &rules).unwrap();
 
         debug!("res: {:?}", res);
 
+        assert_eq!(res.get("row"), Some(&0));
+        assert_eq!(res.get("class"), Some(&1));
+        assert_eq!(res.get("seat"), Some(&2));
+
     }
 
     #[test]
-    fn test_reduce() {
-        let mut input: Vec<Vec<u32>> = vec![
-            vec![0, 0, 1, 1],
-            vec![1, 1, 1, 1],
-            vec![0, 1, 1, 1],
-            vec![0, 0, 0, 1],
+    fn test_vote_system_ambiguous() {
+        init();
+
+        let input = vec![
+            "class: 0-1 or 4-19".to_string(),
+            "row: 0-5 or 8-19".to_string(),
+            "".to_string(),
+            "your ticket:".to_string(),
+            "11,12".to_string(),
+            "".to_string(),
+            "nearby tickets:".to_string(),
+            "9,10".to_string(),
         ];
+        let (tickets, rules) = parse_tickets(input.clone());
 
-        reduce(&mut input);
+        assert!(vote_system(&tickets, &rules).is_err());
+        assert_eq!(problem_162(input), RetType::Failed);
     }
 }
\ No newline at end of file
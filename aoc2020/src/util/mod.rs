@@ -83,7 +83,9 @@ pub enum RetType {
     U32(u32),
     I32(i32),
     U64(u64),
-    U128(u128)
+    U128(u128),
+    /// No answer; the reason has already been logged
+    Failed
 }
 
 impl fmt::Debug for RetType {
@@ -93,6 +95,7 @@ impl fmt::Debug for RetType {
             RetType::I32(x) => write!(f, "{:?}", x),
             RetType::U64(x) => write!(f, "{:?}", x),
             RetType::U128(x) => write!(f, "{:?}", x),
+            RetType::Failed => write!(f, "Failed"),
         }
         
     }
@@ -105,6 +108,7 @@ impl fmt::Display for RetType {
             RetType::I32(x) => write!(f, "{}", x),
             RetType::U64(x) => write!(f, "{}", x),
             RetType::U128(x) => write!(f, "{}", x),
+            RetType::Failed => write!(f, "failed"),
         }
         
     }
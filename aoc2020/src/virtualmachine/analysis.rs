@@ -0,0 +1,262 @@
+use super::opcodes::Flow;
+use super::{Instruction, InstructionCode, Vm};
+
+/// What a program does, as far as can be told without running it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Verdict {
+    /// Every path from pc 0 ends just past the last instruction
+    Terminates,
+    /// No path ends there, and no single flip changes that
+    Loops,
+    /// No path ends there, but one of the flips in `fixes` makes it
+    NeedsFlip,
+    /// Conditional jumps decide, so it depends on the data
+    DependsOnData,
+}
+
+/// A single-instruction change that lets the program terminate
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fix {
+    pub pc: usize,
+    pub from: InstructionCode,
+    pub to: InstructionCode,
+    /// Accumulator at the end of the fixed program, when that can be
+    ///  worked out statically
+    pub acc: Option<i32>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Analysis {
+    /// Instructions no path from pc 0 gets to
+    pub unreachable: Vec<usize>,
+    /// For each instruction, whether some path from it terminates
+    pub terminating: Vec<bool>,
+    pub verdict: Verdict,
+    /// Accumulator once the unmodified program terminates, or just before
+    ///  it repeats an instruction, when that can be worked out statically
+    pub acc: Option<i32>,
+    /// Every flip of a reachable instruction that can make the program
+    ///  terminate, in pc order
+    pub fixes: Vec<Fix>,
+}
+
+/// What an instruction does to the accumulator, if that is fixed.  Only
+///  the boot code opcodes are understood.
+fn acc_effect(inst: &Instruction) -> Option<i32> {
+    match inst.operator {
+        op if op == InstructionCode::ACC => inst.operand(0),
+        op if op == InstructionCode::NOP || op == InstructionCode::JMP => Some(0),
+        _ => None,
+    }
+}
+
+impl Vm {
+    /// Work out what the program does without running it
+    #[allow(dead_code)]
+    pub fn analyze(&self) -> Analysis {
+        let n = self.code.len();
+        let cfg = self.control_flow_graph();
+        let terminating = self.terminating_instructions();
+        let lands_safely = |pc: i32| pc == n as i32 || (pc >= 0 && pc < n as i32 && terminating[pc as usize]);
+
+        let unreachable: Vec<usize> = (0..n).filter(|&i| !cfg.reachable[i]).collect();
+        let deterministic = (0..n)
+            .filter(|&i| cfg.reachable[i])
+            .all(|i| self.code[i].operator.opcode().flow != Flow::Branch);
+
+        // The one path a program without conditional jumps takes, up to the
+        //  point it leaves the program or comes back on itself
+        let mut path = Vec::new();
+        let mut acc: Option<i32> = Some(0);
+        if deterministic {
+            let mut seen = vec![false; n];
+            let mut pc: i32 = 0;
+            while pc >= 0 && (pc as usize) < n && !seen[pc as usize] {
+                seen[pc as usize] = true;
+                path.push(pc as usize);
+                let inst = &self.code[pc as usize];
+                acc = acc.and_then(|a| acc_effect(inst).map(|x| a.wrapping_add(x)));
+                pc = inst.successors(pc, n)[0];
+            }
+        }
+
+        let always_terminates = n == 0 || (deterministic && terminating[0]) || (!deterministic && self.reachable_part_terminates(&cfg.reachable));
+        let never_terminates = n > 0 && !terminating[0];
+
+        let mut fixes = Vec::new();
+        if never_terminates {
+            // Everything reachable loops or falls out of the program, so
+            //  no terminating path goes through the instruction that gets
+            //  flipped and the old analysis still holds after the flip.
+            let acc_to_end = if deterministic { self.acc_to_end(&terminating) } else { vec![None; n] };
+            let mut acc_before: Vec<Option<i32>> = vec![None; n];
+            let mut running: Option<i32> = Some(0);
+            for &pc in &path {
+                acc_before[pc] = running;
+                running = running.and_then(|a| acc_effect(&self.code[pc]).map(|x| a.wrapping_add(x)));
+            }
+
+            for pc in (0..n).filter(|&i| cfg.reachable[i]) {
+                let inst = self.code[pc];
+                let to = match inst.operator.flipped() {
+                    Some(to) => to,
+                    None => continue,
+                };
+                let patched = inst.with_operator(to);
+                let targets = patched.successors(pc as i32, n);
+                if !targets.iter().any(|&t| lands_safely(t)) {
+                    continue;
+                }
+                let acc = match (deterministic, acc_before[pc], acc_effect(&patched)) {
+                    (true, Some(before), Some(own)) => {
+                        let t = targets[0];
+                        (if t == n as i32 { Some(0) } else { acc_to_end[t as usize] }).map(|rest| before.wrapping_add(own).wrapping_add(rest))
+                    },
+                    _ => None,
+                };
+                fixes.push(Fix { pc, from: inst.operator, to, acc });
+            }
+        }
+
+        let verdict = if always_terminates {
+            Verdict::Terminates
+        } else if never_terminates && fixes.is_empty() {
+            Verdict::Loops
+        } else if never_terminates {
+            Verdict::NeedsFlip
+        } else {
+            Verdict::DependsOnData
+        };
+
+        Analysis {
+            unreachable,
+            terminating,
+            verdict,
+            acc: if deterministic { acc } else { None },
+            fixes,
+        }
+    }
+
+    /// Does every path through the reachable instructions end just past
+    ///  the last one?  True when they hold no cycle and never jump out of
+    ///  the program.
+    fn reachable_part_terminates(&self, reachable: &[bool]) -> bool {
+        let n = self.code.len();
+        // Kahn's algorithm over the reachable instructions
+        let mut indegree = vec![0; n];
+        for i in (0..n).filter(|&i| reachable[i]) {
+            for t in self.code[i].successors(i as i32, n) {
+                if t < 0 || t > n as i32 {
+                    return false;
+                }
+                if t < n as i32 {
+                    indegree[t as usize] += 1;
+                }
+            }
+        }
+        let mut ready: Vec<usize> = (0..n).filter(|&i| reachable[i] && indegree[i] == 0).collect();
+        let mut done = 0;
+        while let Some(i) = ready.pop() {
+            done += 1;
+            for t in self.code[i].successors(i as i32, n) {
+                if t < n as i32 {
+                    indegree[t as usize] -= 1;
+                    if indegree[t as usize] == 0 {
+                        ready.push(t as usize);
+                    }
+                }
+            }
+        }
+        done == reachable.iter().filter(|&&r| r).count()
+    }
+
+    /// Accumulator change from each terminating instruction to the end, for
+    ///  code without conditional jumps.  None where it can't be told.
+    fn acc_to_end(&self, terminating: &[bool]) -> Vec<Option<i32>> {
+        let n = self.code.len();
+        let mut result: Vec<Option<i32>> = vec![None; n];
+        let mut done = vec![false; n];
+
+        for start in (0..n).filter(|&i| terminating[i]) {
+            // Follow the path to the end or to something already worked
+            //  out, then fill in backwards
+            let mut chain = Vec::new();
+            let mut pc = start as i32;
+            while pc >= 0 && (pc as usize) < n && !done[pc as usize] {
+                chain.push(pc as usize);
+                done[pc as usize] = true;
+                pc = self.code[pc as usize].successors(pc, n)[0];
+            }
+            let mut rest = if pc as usize == n { Some(0) } else { result[pc as usize] };
+            for &i in chain.iter().rev() {
+                rest = rest.and_then(|r| acc_effect(&self.code[i]).map(|x| r.wrapping_add(x)));
+                result[i] = rest;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::day8_example;
+
+    fn program(lines: &[&str]) -> Vm {
+        Vm::from_source(lines.iter().map(|x| x.to_string()).collect()).unwrap()
+    }
+
+    #[test]
+    fn test_analyze_example() {
+        let mut vm = day8_example();
+        let analysis = vm.analyze();
+
+        assert_eq!(analysis.unreachable, vec![5, 8]);
+        assert_eq!(analysis.terminating, vec![false, false, false, false, false, false, false, false, true]);
+        assert_eq!(analysis.verdict, Verdict::NeedsFlip);
+        assert_eq!(analysis.acc, Some(5));
+        assert_eq!(analysis.fixes, vec![Fix { pc: 7, from: InstructionCode::JMP, to: InstructionCode::NOP, acc: Some(8) }]);
+
+        // Same answers as actually running it
+        vm.run();
+        assert_eq!(Some(vm.get_acc()), analysis.acc);
+        assert_eq!(vm.execute_gamegirl(), crate::virtualmachine::Halt::Terminated { acc: 8 });
+    }
+
+    #[test]
+    fn test_analyze_verdicts() {
+        let analysis = program(&["acc +2", "jmp +2", "acc +5", "nop -2"]).analyze();
+        assert_eq!((analysis.verdict, analysis.acc), (Verdict::Terminates, Some(2)));
+        assert_eq!(analysis.unreachable, vec![2]);
+        assert!(analysis.fixes.is_empty());
+
+        // Every flip leaves it stuck
+        let analysis = program(&["jmp +0", "jmp -1"]).analyze();
+        assert_eq!((analysis.verdict, analysis.acc), (Verdict::Loops, Some(0)));
+        let analysis = program(&["acc +1", "jmp -1", "acc +2"]).analyze();
+        assert_eq!(analysis.verdict, Verdict::NeedsFlip);
+        assert_eq!(analysis.fixes.iter().map(|f| (f.pc, f.acc)).collect::<Vec<_>>(), vec![(1, Some(3))]);
+
+        // More than one flip works
+        let analysis = program(&["nop +2", "jmp +0", "acc +4"]).analyze();
+        assert_eq!(analysis.fixes.iter().map(|f| (f.pc, f.acc)).collect::<Vec<_>>(), vec![(0, Some(4)), (1, Some(4))]);
+
+        // Falling out of the program is not terminating either
+        let analysis = program(&["acc +1", "jmp -5"]).analyze();
+        assert_eq!(analysis.verdict, Verdict::NeedsFlip);
+
+        let analysis = program(&["in a", "jnz a +2", "jmp -2", "hlt"]).analyze();
+        assert_eq!((analysis.verdict, analysis.acc), (Verdict::DependsOnData, None));
+        let analysis = program(&["set b 2", "jnz b +2", "acc +1", "hlt"]).analyze();
+        assert_eq!(analysis.verdict, Verdict::Terminates);
+    }
+
+    #[test]
+    fn test_analyze_wraps_like_the_vm() {
+        let mut vm = program(&["acc +2147483647", "acc +1", "jmp -2"]);
+        let analysis = vm.analyze();
+        assert_eq!(analysis.acc, Some(i32::MIN));
+        assert_eq!(analysis.fixes.iter().map(|f| (f.pc, f.acc)).collect::<Vec<_>>(), vec![(2, Some(i32::MIN))]);
+        assert_eq!(vm.run(), crate::virtualmachine::Halt::InfiniteLoop { pc: 0, acc: i32::MIN });
+    }
+}
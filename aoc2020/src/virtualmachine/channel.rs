@@ -0,0 +1,124 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+/// Somewhere `in` can read values from
+pub trait Input {
+    /// Next value, or None if there is nothing to read yet
+    fn receive(&mut self) -> Option<i32>;
+}
+
+/// Somewhere `out` can write values to
+pub trait Output {
+    fn send(&mut self, value: i32);
+
+    /// Take back the last value sent, when stepping backwards.  Returns
+    ///  false if that isn't possible, e.g. because it was already read.
+    fn retract(&mut self) -> bool {
+        false
+    }
+}
+
+/// Queue behind a Channel.  Every value gets a sequence number so a sender
+///  can tell its own value apart from an equal one someone else sent.
+#[derive(Default)]
+struct Shared {
+    queue: VecDeque<(u64, i32)>,
+    next_seq: u64,
+}
+
+/// A FIFO queue shared between whoever holds a clone of it.  Used to wire
+///  the output of one VM to the input of another.  Each clone remembers
+///  what it sent itself, so several producers can share one channel.
+#[derive(Default)]
+pub struct Channel {
+    shared: Rc<RefCell<Shared>>,
+    sent: Vec<u64>,
+}
+
+/// A new handle on the same queue, which hasn't sent anything yet
+impl Clone for Channel {
+    fn clone(&self) -> Channel {
+        Channel { shared: Rc::clone(&self.shared), sent: Vec::new() }
+    }
+}
+
+#[allow(dead_code)]
+impl Channel {
+    pub fn new() -> Channel {
+        Channel::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.borrow().queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shared.borrow().queue.is_empty()
+    }
+
+    /// Everything waiting to be read, oldest first
+    pub fn contents(&self) -> Vec<i32> {
+        self.shared.borrow().queue.iter().map(|&(_, x)| x).collect()
+    }
+
+    /// Queue a value, returning its sequence number
+    pub fn push(&self, value: i32) -> u64 {
+        let mut shared = self.shared.borrow_mut();
+        let seq = shared.next_seq;
+        shared.next_seq += 1;
+        shared.queue.push_back((seq, value));
+        seq
+    }
+
+    pub fn pop(&self) -> Option<i32> {
+        self.shared.borrow_mut().queue.pop_front().map(|(_, x)| x)
+    }
+}
+
+impl Input for Channel {
+    fn receive(&mut self) -> Option<i32> {
+        self.pop()
+    }
+}
+
+impl Output for Channel {
+    fn send(&mut self, value: i32) {
+        let seq = self.push(value);
+        self.sent.push(seq);
+    }
+
+    /// Only while the last value this handle sent is still at the back:
+    ///  not once it has been read, nor once anyone has sent after it
+    fn retract(&mut self) -> bool {
+        let seq = match self.sent.pop() {
+            Some(seq) => seq,
+            None => return false,
+        };
+        let mut shared = self.shared.borrow_mut();
+        if shared.queue.back().map(|&(s, _)| s) == Some(seq) {
+            shared.queue.pop_back();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Reads from a fixed list of values
+impl Input for VecDeque<i32> {
+    fn receive(&mut self) -> Option<i32> {
+        self.pop_front()
+    }
+}
+
+/// Collects everything sent
+impl Output for Vec<i32> {
+    fn send(&mut self, value: i32) {
+        self.push(value);
+    }
+
+    fn retract(&mut self) -> bool {
+        self.pop().is_some()
+    }
+}
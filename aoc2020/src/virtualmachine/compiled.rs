@@ -0,0 +1,439 @@
+use std::collections::VecDeque;
+use super::opcodes::{register_index, Context, Control};
+use super::{jump_target, Halt, Instruction, InstructionCode, Opcode, Operand, Vm, REGISTERS};
+
+/// A register or a number, already looked up
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Val {
+    Imm(i32),
+    Reg(u8),
+}
+
+/// Pre-decoded instruction.  The standard opcodes get their own variant so
+///  running them is a single match; anything registered later goes through
+///  its table entry like the interpreter does.
+#[derive(Clone, Copy)]
+enum Op {
+    Nop,
+    Acc(i32),
+    Jmp(i32),
+    Set(u8, Val),
+    Add(u8, Val),
+    Mul(u8, Val),
+    Jnz(Val, i32),
+    Jz(Val, i32),
+    Hlt,
+    In(u8),
+    Out(Val),
+    Other(&'static Opcode, Instruction),
+}
+
+fn val(operand: Operand) -> Val {
+    match operand {
+        Operand::Imm(x) => Val::Imm(x),
+        Operand::Reg(r) => Val::Reg(r as u8),
+    }
+}
+
+fn reg(operand: Operand) -> u8 {
+    match operand {
+        Operand::Reg(r) => r as u8,
+        Operand::Imm(_) => panic!("Operand should have been parsed as a register"),
+    }
+}
+
+fn imm(operand: Operand) -> i32 {
+    match operand {
+        Operand::Imm(x) => x,
+        Operand::Reg(_) => panic!("Operand should have been parsed as a number"),
+    }
+}
+
+fn decode(inst: &Instruction) -> Op {
+    let op = inst.operator;
+    let args = inst.operands();
+    if op == InstructionCode::NOP {
+        Op::Nop
+    } else if op == InstructionCode::ACC {
+        Op::Acc(imm(args[0]))
+    } else if op == InstructionCode::JMP {
+        Op::Jmp(imm(args[0]))
+    } else if op == InstructionCode::SET {
+        Op::Set(reg(args[0]), val(args[1]))
+    } else if op == InstructionCode::ADD {
+        Op::Add(reg(args[0]), val(args[1]))
+    } else if op == InstructionCode::MUL {
+        Op::Mul(reg(args[0]), val(args[1]))
+    } else if op == InstructionCode::JNZ {
+        Op::Jnz(val(args[0]), imm(args[1]))
+    } else if op == InstructionCode::JZ {
+        Op::Jz(val(args[0]), imm(args[1]))
+    } else if op == InstructionCode::HLT {
+        Op::Hlt
+    } else if op == InstructionCode::IN {
+        Op::In(reg(args[0]))
+    } else if op == InstructionCode::OUT {
+        Op::Out(val(args[0]))
+    } else {
+        Op::Other(op.opcode(), *inst)
+    }
+}
+
+/// Fast version of Vm for big programs.  Behaves the same, but doesn't
+///  log, can't step backwards, only keeps a history if asked to, and only
+///  reads and writes its own queues, never a connected source or sink.
+#[derive(Clone)]
+pub struct CompiledVm {
+    ops: Vec<Op>,
+    pc: i32,
+    registers: [i32; REGISTERS.len()],
+    input: VecDeque<i32>,
+    output: Vec<i32>,
+    visited: Vec<bool>,
+    steps: usize,
+    history: Option<Vec<i32>>,
+}
+
+impl Vm {
+    /// Decode the program for the fast backend.  Registers, queued input,
+    ///  output, the step count and which instructions have run carry over;
+    ///  the history doesn't.  Fails if a source or sink is connected, since
+    ///  the fast backend has no way to reach them.
+    #[allow(dead_code)]
+    pub fn compile(&self) -> Result<CompiledVm, String> {
+        if self.source.is_some() || self.sink.is_some() {
+            return Err("Can't compile a VM connected to a source or sink".to_string());
+        }
+        let mut registers = [0; REGISTERS.len()];
+        registers.copy_from_slice(&self.registers);
+        Ok(CompiledVm {
+            ops: self.code.iter().map(decode).collect(),
+            pc: self.pc,
+            registers,
+            input: self.input.clone(),
+            output: self.output.clone(),
+            visited: self.visits.iter().map(|&x| x > 0).collect(),
+            steps: self.steps(),
+            history: None,
+        })
+    }
+}
+
+#[allow(dead_code)]
+impl CompiledVm {
+    /// Keep the program counter of every executed instruction from now on
+    pub fn record_history(&mut self) {
+        if self.history.is_none() {
+            self.history = Some(Vec::new());
+        }
+    }
+
+    fn read(&self, v: Val) -> i32 {
+        match v {
+            Val::Imm(x) => x,
+            Val::Reg(r) => self.registers[r as usize],
+        }
+    }
+
+    /// As Vm::execute_once
+    #[inline]
+    pub fn execute_once(&mut self) -> Option<Halt> {
+        let n = self.ops.len() as i32;
+        if self.pc == n {
+            return Some(Halt::Terminated { acc: self.registers[0] });
+        } else if self.pc < 0 || self.pc > n {
+            return Some(Halt::OutOfBounds { pc: self.pc });
+        }
+
+        let pc = self.pc;
+        self.pc = match self.ops[pc as usize] {
+            Op::Nop => pc + 1,
+            Op::Acc(x) => {
+                self.registers[0] = self.registers[0].wrapping_add(x);
+                pc + 1
+            },
+            Op::Jmp(x) => jump_target(pc, x),
+            Op::Set(r, v) => {
+                self.registers[r as usize] = self.read(v);
+                pc + 1
+            },
+            Op::Add(r, v) => {
+                self.registers[r as usize] = self.registers[r as usize].wrapping_add(self.read(v));
+                pc + 1
+            },
+            Op::Mul(r, v) => {
+                self.registers[r as usize] = self.registers[r as usize].wrapping_mul(self.read(v));
+                pc + 1
+            },
+            Op::Jnz(v, x) => if self.read(v) != 0 { jump_target(pc, x) } else { pc + 1 },
+            Op::Jz(v, x) => if self.read(v) == 0 { jump_target(pc, x) } else { pc + 1 },
+            Op::Hlt => n,
+            Op::In(r) => match self.input.pop_front() {
+                Some(value) => {
+                    self.registers[r as usize] = value;
+                    pc + 1
+                },
+                None => return Some(Halt::Blocked { pc }),
+            },
+            Op::Out(v) => {
+                let value = self.read(v);
+                self.output.push(value);
+                pc + 1
+            },
+            Op::Other(opcode, inst) => match self.run_other(opcode, &inst) {
+                Control::Next => pc + 1,
+                Control::Jump(x) => jump_target(pc, x),
+                Control::Halt => n,
+                Control::Block => return Some(Halt::Blocked { pc }),
+            },
+        };
+
+        self.visited[pc as usize] = true;
+        self.steps += 1;
+        if let Some(history) = self.history.as_mut() {
+            history.push(pc);
+        }
+        None
+    }
+
+    fn run_other(&mut self, opcode: &'static Opcode, inst: &Instruction) -> Control {
+        let mut ctx = Context {
+            registers: &mut self.registers,
+            input: &mut self.input,
+            source: &mut None,
+            output: &mut self.output,
+            sink: &mut None,
+            writes: Vec::new(),
+            consumed: None,
+            produced: false,
+        };
+        (opcode.exec)(&mut ctx, inst.operands())
+    }
+
+    /// As Vm::run_with_limit
+    pub fn run_with_limit(&mut self, max_steps: usize) -> Halt {
+        let n = self.ops.len() as i32;
+        for _ in 0..max_steps {
+            if self.pc >= 0 && self.pc < n && self.visited[self.pc as usize] {
+                return Halt::InfiniteLoop { pc: self.pc, acc: self.registers[0] };
+            }
+            if let Some(halt) = self.execute_once() {
+                return halt;
+            }
+        }
+        Halt::StepLimit
+    }
+
+    /// As Vm::run
+    pub fn run(&mut self) -> Halt {
+        self.run_with_limit(usize::MAX)
+    }
+
+    /// As Vm::run_until_halt
+    pub fn run_until_halt(&mut self, max_steps: usize) -> Halt {
+        for _ in 0..max_steps {
+            if let Some(halt) = self.execute_once() {
+                return halt;
+            }
+        }
+        Halt::StepLimit
+    }
+
+    pub fn get_acc(&self) -> i32 {
+        self.registers[0]
+    }
+
+    pub fn get_pc(&self) -> i32 {
+        self.pc
+    }
+
+    pub fn get_register(&self, name: &str) -> Option<i32> {
+        register_index(name).map(|r| self.registers[r])
+    }
+
+    pub fn push_input(&mut self, value: i32) {
+        self.input.push_back(value);
+    }
+
+    pub fn get_output(&self) -> &[i32] {
+        &self.output
+    }
+
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// Program counters executed since record_history was called
+    pub fn get_history(&self) -> Option<&[i32]> {
+        self.history.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{Control, Flow, InstructionSet, OperandKind, ParseMode, DAY8_EXAMPLE};
+    use std::time::Instant;
+
+    fn program(lines: &[&str]) -> Vm {
+        Vm::from_source(lines.iter().map(|x| x.to_string()).collect()).unwrap()
+    }
+
+    /// Run both backends the same way and check they end up in the same
+    ///  place
+    fn assert_same(mut vm: Vm, input: &[i32], detect_loops: bool) {
+        for &x in input {
+            vm.push_input(x);
+        }
+        vm.record_history();
+        let mut fast = vm.compile().unwrap();
+        fast.record_history();
+
+        let (slow_halt, fast_halt) = if detect_loops {
+            (vm.run_with_limit(10_000), fast.run_with_limit(10_000))
+        } else {
+            (vm.run_until_halt(10_000), fast.run_until_halt(10_000))
+        };
+
+        assert_eq!(slow_halt, fast_halt);
+        assert_eq!(vm.get_pc(), fast.get_pc());
+        assert_eq!(vm.steps(), fast.steps());
+        assert_eq!(vm.get_history(), fast.get_history().unwrap());
+        assert_eq!(vm.get_output(), fast.get_output());
+        for name in REGISTERS.iter() {
+            assert_eq!(vm.get_register(name), fast.get_register(name));
+        }
+    }
+
+    #[test]
+    fn test_compiled_matches_boot_code() {
+        let example = DAY8_EXAMPLE;
+        assert_same(program(&example), &[], true);
+
+        // Every single flip of the example, which covers terminating,
+        //  looping and out of bounds runs
+        for pc in 0..example.len() {
+            let mut lines: Vec<String> = example.iter().map(|x| x.to_string()).collect();
+            lines[pc] = match &lines[pc][..3] {
+                "jmp" => lines[pc].replace("jmp", "nop"),
+                "nop" => lines[pc].replace("nop", "jmp"),
+                _ => continue,
+            };
+            assert_same(Vm::from_source(lines).unwrap(), &[], true);
+        }
+
+        assert_same(program(&["acc +1", "jmp +7"]), &[], true);
+        assert_same(program(&["acc +2147483647", "acc +1"]), &[], true);
+        assert_same(program(&["nop +0", "jmp +2147483647"]), &[], true);
+        assert_same(program(&["set b 1", "jnz b +2147483647"]), &[], false);
+    }
+
+    #[test]
+    fn test_compiled_matches_extended() {
+        let factorial = ["in b", "set a 1", "mul a b", "add b -1", "jnz b -2", "out a", "hlt", "acc +100"];
+        assert_same(program(&factorial), &[6], false);
+        assert_same(program(&factorial), &[], false);
+        assert_same(program(&["in a", "jz a +2", "out a", "jmp -3"]), &[3, 2, 0, 7], false);
+        assert_same(program(&["add a 1", "jmp -1"]), &[], false);
+        assert_same(program(&["add a 1", "jmp -1"]), &[], true);
+    }
+
+    static NEG: Opcode = Opcode {
+        mnemonic: "neg",
+        operands: &[OperandKind::Reg],
+        flow: Flow::Next,
+        flip: None,
+        exec: |ctx, ops| {
+            if let Operand::Reg(r) = ops[0] {
+                let value = -ctx.read(ops[0]);
+                ctx.write(r, value);
+            }
+            Control::Next
+        },
+    };
+
+    /// Not the built-in out, despite the name
+    static SCALE: Opcode = Opcode {
+        mnemonic: "out",
+        operands: &[OperandKind::Value],
+        flow: Flow::Next,
+        flip: None,
+        exec: |ctx, _| {
+            let value = ctx.read(Operand::Reg(0)).wrapping_mul(100);
+            ctx.write(0, value);
+            Control::Next
+        },
+    };
+
+    #[test]
+    fn test_compiled_registered_opcode() {
+        let mut isa = InstructionSet::standard();
+        isa.register(&NEG).unwrap();
+        let lines = vec!["set c 4".to_string(), "neg c".to_string(), "out c".to_string()];
+        let vm = Vm::from_source_with_set(lines, ParseMode::Strict, isa).unwrap();
+        assert_same(vm, &[], false);
+
+        let mut isa = InstructionSet::boot();
+        isa.register(&SCALE).unwrap();
+        let lines = vec!["acc +3".to_string(), "out a".to_string()];
+        let vm = Vm::from_source_with_set(lines.clone(), ParseMode::Strict, isa.clone()).unwrap();
+        let mut fast = vm.compile().unwrap();
+        assert_eq!(fast.run(), Halt::Terminated { acc: 300 });
+        assert!(fast.get_output().is_empty());
+        assert_same(Vm::from_source_with_set(lines, ParseMode::Strict, isa).unwrap(), &[], false);
+    }
+
+    #[test]
+    fn test_compiled_carries_state() {
+        // Half way round a loop, the fast backend still knows where it's been
+        let mut vm = program(&["acc +1", "acc +2", "jmp -2"]);
+        vm.execute_once();
+        vm.execute_once();
+        let mut fast = vm.compile().unwrap();
+        assert_eq!(fast.steps(), 2);
+        assert_eq!(fast.run(), vm.run());
+        assert_eq!(fast.steps(), vm.steps());
+
+        let mut vm = program(&["out a"]);
+        vm.set_output(Vec::new());
+        assert!(vm.compile().is_err());
+    }
+
+    #[test]
+    fn test_compiled_no_history_by_default() {
+        let mut fast = program(&["acc +1", "acc +2"]).compile().unwrap();
+        assert_eq!(fast.run(), Halt::Terminated { acc: 3 });
+        assert_eq!(fast.get_history(), None);
+        assert_eq!(fast.steps(), 2);
+    }
+
+    /// Rough timing of both backends on the day 8 input.  The program is
+    ///  decoded once and each run starts from a copy, as each interpreted
+    ///  run starts from a reset.  Run with
+    ///  `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn test_compiled_timing() {
+        const RUNS: usize = 10_000;
+        let lines = crate::util::load_file(concat!(env!("CARGO_MANIFEST_DIR"), "/inputs/08.txt").to_string());
+        let mut vm = Vm::from_source(lines).unwrap();
+
+        let start = Instant::now();
+        let slow: Vec<Halt> = (0..RUNS).map(|_| {
+            vm.reset();
+            vm.run()
+        }).collect();
+        let interpreted = start.elapsed();
+
+        vm.reset();
+        let start = Instant::now();
+        let decoded = vm.compile().unwrap();
+        let decoding = start.elapsed();
+        let start = Instant::now();
+        let fast: Vec<Halt> = (0..RUNS).map(|_| decoded.clone().run()).collect();
+        let compiled = start.elapsed();
+
+        assert_eq!(fast, slow);
+        println!("{} runs of day 8: interpreted {:?}, compiled {:?} after {:?} decoding", RUNS, interpreted, compiled, decoding);
+    }
+}
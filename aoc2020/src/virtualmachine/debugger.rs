@@ -0,0 +1,496 @@
+use std::fmt;
+use std::io;
+use std::io::{BufRead, Write};
+use super::{parse, Halt, InstructionCode, Vm};
+
+/// Comparison used by accumulator breakpoints
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    fn parse(s: &str) -> Option<Comparison> {
+        match s {
+            "==" | "=" => Some(Comparison::Eq),
+            "!=" => Some(Comparison::Ne),
+            "<" => Some(Comparison::Lt),
+            "<=" => Some(Comparison::Le),
+            ">" => Some(Comparison::Gt),
+            ">=" => Some(Comparison::Ge),
+            _ => None,
+        }
+    }
+
+    fn holds(&self, a: i32, b: i32) -> bool {
+        match self {
+            Comparison::Eq => a == b,
+            Comparison::Ne => a != b,
+            Comparison::Lt => a < b,
+            Comparison::Le => a <= b,
+            Comparison::Gt => a > b,
+            Comparison::Ge => a >= b,
+        }
+    }
+
+    fn symbol(&self) -> &'static str {
+        match self {
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        }
+    }
+}
+
+/// Where continue should stop.  Checked before each instruction runs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Breakpoint {
+    Pc(i32),
+    Opcode(InstructionCode),
+    Acc(Comparison, i32),
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Breakpoint::Pc(pc) => write!(f, "pc {}", pc),
+            Breakpoint::Opcode(op) => write!(f, "op {}", op),
+            Breakpoint::Acc(cmp, value) => write!(f, "acc {} {}", cmp.symbol(), value),
+        }
+    }
+}
+
+const HELP: &str = "\
+step [n]              run n instructions (default 1)
+continue [n]          run until a breakpoint or the program stops, for at
+                      most n instructions (default 1000000)
+back [n]              undo n instructions (default 1)
+rcontinue             run backwards until a breakpoint or the start
+rewind <step>         undo instructions until only `step` of them have run
+break <pc>            stop before the instruction at pc
+break op <opcode>     stop before any instruction with this opcode
+break acc <cmp> <n>   stop once the accumulator compares true (== != < <= > >=)
+delete <n>            remove breakpoint n
+info                  list breakpoints
+print                 show registers
+history [n]           show the last n program counters (default 10)
+list [pc]             show the code around pc
+patch <pc> <inst>     replace an instruction, e.g. patch 7 nop -4
+profile on|off        start or stop counting executions
+profile               show execution counts next to the disassembly
+profile csv <file>    save execution counts as CSV
+boot on|off           stop continue before any instruction runs a second
+                      time, as the day 8 boot code does
+reset                 start the program over
+quit                  leave the debugger";
+
+/// Interactive step debugger around a Vm.  Commands are handled by
+///  `command`, which returns the text to show, so the REPL itself is only
+///  a thin loop over stdin.
+pub struct Debugger {
+    vm: Vm,
+    breakpoints: Vec<Breakpoint>,
+    /// Treat the first repeated instruction as an infinite loop
+    boot: bool,
+}
+
+/// Instructions continue runs before giving up, unless told otherwise
+const CONTINUE_LIMIT: usize = 1_000_000;
+
+impl Debugger {
+    pub fn new(mut vm: Vm) -> Debugger {
+        vm.record_history();
+        Debugger {
+            vm,
+            breakpoints: Vec::new(),
+            boot: false,
+        }
+    }
+
+    /// Start in boot code mode, see the boot command
+    pub fn boot_mode(mut self, on: bool) -> Debugger {
+        self.boot = on;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn vm(&self) -> &Vm {
+        &self.vm
+    }
+
+    fn registers(&self) -> String {
+        format!("pc = {}, acc = {}, steps = {}", self.vm.pc, self.vm.get_acc(), self.vm.steps())
+    }
+
+    fn describe_halt(&self, halt: Halt) -> String {
+        match halt {
+            Halt::Terminated { acc } => format!("Program terminated; acc = {}", acc),
+            Halt::InfiniteLoop { pc, acc } => format!("Infinite loop: pc {} is about to run again; acc = {}", pc, acc),
+            Halt::OutOfBounds { pc } => format!("Program counter out of bounds: {}", pc),
+            Halt::Blocked { pc } => format!("Waiting for input at pc {}", pc),
+            Halt::StepLimit => "Step limit reached".to_string(),
+        }
+    }
+
+    /// Breakpoint that applies to the state the VM is in right now
+    fn hit_breakpoint(&self) -> Option<usize> {
+        let inst = self.vm.code.get(self.vm.pc as usize).filter(|_| self.vm.pc >= 0);
+        self.breakpoints.iter().position(|bp| match *bp {
+            Breakpoint::Pc(pc) => pc == self.vm.pc,
+            Breakpoint::Opcode(op) => inst.is_some_and(|x| x.operator == op),
+            Breakpoint::Acc(cmp, value) => cmp.holds(self.vm.get_acc(), value),
+        })
+    }
+
+    /// Run one instruction, or say why there isn't one to run
+    fn step_once(&mut self) -> Result<(), Halt> {
+        match self.vm.execute_once() {
+            Some(halt) => Err(halt),
+            None => Ok(()),
+        }
+    }
+
+    fn step(&mut self, n: usize) -> String {
+        for _ in 0..n {
+            if let Err(halt) = self.step_once() {
+                return self.describe_halt(halt);
+            }
+        }
+        format!("{}\n{}", self.registers(), self.listing(self.vm.pc, 0))
+    }
+
+    /// Run until a breakpoint matches, the program stops or max_steps
+    ///  instructions have run.  Only in boot mode does coming back to an
+    ///  instruction count as stopping.
+    fn cont(&mut self, max_steps: usize) -> String {
+        for i in 0..max_steps {
+            if i > 0 {
+                if let Some(i) = self.hit_breakpoint() {
+                    return format!("Breakpoint {} ({})\n{}\n{}", i, self.breakpoints[i],
+                        self.registers(), self.listing(self.vm.pc, 0));
+                }
+            }
+
+            let pc = self.vm.pc;
+            if self.boot && self.vm.has_visited(pc) {
+                return self.describe_halt(Halt::InfiniteLoop { pc, acc: self.vm.get_acc() });
+            }
+            if let Err(halt) = self.step_once() {
+                return self.describe_halt(halt);
+            }
+        }
+        format!("{}\n{}", self.describe_halt(Halt::StepLimit), self.registers())
+    }
+
+    fn back(&mut self, n: usize) -> String {
+        for _ in 0..n {
+            if !self.vm.step_back() {
+                return format!("At the start of the program\n{}", self.registers());
+            }
+        }
+        format!("{}\n{}", self.registers(), self.listing(self.vm.pc, 0))
+    }
+
+    /// Run backwards until a breakpoint matches or the start is reached
+    fn reverse_cont(&mut self) -> String {
+        while self.vm.step_back() {
+            if let Some(i) = self.hit_breakpoint() {
+                return format!("Breakpoint {} ({})\n{}\n{}", i, self.breakpoints[i],
+                    self.registers(), self.listing(self.vm.pc, 0));
+            }
+        }
+        format!("At the start of the program\n{}", self.registers())
+    }
+
+    /// The code from `radius` instructions before pc to `radius` after it,
+    ///  with the current instruction marked
+    fn listing(&self, pc: i32, radius: i32) -> String {
+        let lo = pc.saturating_sub(radius).max(0);
+        let hi = pc.saturating_add(radius).min(self.vm.code.len() as i32 - 1);
+        let mut lines = Vec::new();
+        for i in lo..=hi {
+            let marker = if i == self.vm.pc { "=>" } else { "  " };
+            lines.push(format!("{} {:>5}: {}", marker, i, self.vm.code[i as usize]));
+        }
+        lines.join("\n")
+    }
+
+    fn add_breakpoint(&mut self, args: &[&str]) -> Result<String, String> {
+        let bp = match args {
+            [pc] => Breakpoint::Pc(pc.parse::<i32>().map_err(|_| format!("Bad pc `{}`", pc))?),
+            ["op", op] => Breakpoint::Opcode(parse::parse_opcode(&self.vm.isa, op).ok_or(format!("Unknown opcode `{}`", op))?),
+            ["acc", cmp, value] => Breakpoint::Acc(
+                Comparison::parse(cmp).ok_or(format!("Unknown comparison `{}`", cmp))?,
+                value.parse::<i32>().map_err(|_| format!("Bad value `{}`", value))?,
+            ),
+            _ => return Err("Usage: break <pc> | break op <opcode> | break acc <cmp> <value>".to_string()),
+        };
+        self.breakpoints.push(bp);
+        Ok(format!("Breakpoint {} ({})", self.breakpoints.len() - 1, bp))
+    }
+
+    fn patch(&mut self, args: &[&str]) -> Result<String, String> {
+        if args.len() < 2 {
+            return Err("Usage: patch <pc> <instruction>".to_string());
+        }
+        let pc = args[0].parse::<usize>().ok()
+            .filter(|&x| x < self.vm.code.len())
+            .ok_or(format!("Bad pc `{}`", args[0]))?;
+        let inst = parse::parse_line(&self.vm.isa, &args[1..].join(" "), 1).map_err(|e| e.to_string())?;
+        let old = self.vm.code[pc];
+        self.vm.code[pc] = inst;
+        Ok(format!("{:>5}: {} -> {}", pc, old, inst))
+    }
+
+    fn profile(&mut self, args: &[&str]) -> Result<String, String> {
+        match args {
+            ["on"] => {
+                self.vm.enable_profiling();
+                Ok("Profiling on".to_string())
+            },
+            ["off"] => {
+                self.vm.disable_profiling();
+                Ok("Profiling off".to_string())
+            },
+            [] => self.vm.profile_report()
+                .map(|report| report.to_string().trim_end().to_string())
+                .ok_or_else(|| "Profiling is off; try profile on".to_string()),
+            ["csv", path] => {
+                let report = self.vm.profile_report().ok_or("Profiling is off; try profile on")?;
+                std::fs::write(path, report.to_csv()).map_err(|e| format!("Can't write {}: {}", path, e))?;
+                Ok(format!("Wrote {}", path))
+            },
+            _ => Err("Usage: profile [on|off|csv <file>]".to_string()),
+        }
+    }
+
+    /// Handle one command line.  Returns None once the user asks to quit.
+    pub fn command(&mut self, line: &str) -> Option<String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (cmd, args) = match words.split_first() {
+            Some((cmd, args)) => (*cmd, args),
+            None => return Some(String::new()),
+        };
+
+        let out = match cmd {
+            "s" | "step" => match args.first().map(|x| x.parse::<usize>()) {
+                None => Ok(self.step(1)),
+                Some(Ok(n)) => Ok(self.step(n)),
+                Some(Err(_)) => Err("Usage: step [n]".to_string()),
+            },
+            "c" | "continue" => match args.first().map(|x| x.parse::<usize>()) {
+                None => Ok(self.cont(CONTINUE_LIMIT)),
+                Some(Ok(n)) => Ok(self.cont(n)),
+                Some(Err(_)) => Err("Usage: continue [n]".to_string()),
+            },
+            "back" => match args.first().map(|x| x.parse::<usize>()) {
+                None => Ok(self.back(1)),
+                Some(Ok(n)) => Ok(self.back(n)),
+                Some(Err(_)) => Err("Usage: back [n]".to_string()),
+            },
+            "rc" | "rcontinue" => Ok(self.reverse_cont()),
+            "rewind" => match args.first().map(|x| x.parse::<usize>()) {
+                Some(Ok(n)) if self.vm.rewind_to(n) => Ok(format!("{}\n{}", self.registers(), self.listing(self.vm.pc, 0))),
+                Some(Ok(n)) => Err(format!("Only {} steps have run", n.min(self.vm.steps()))),
+                _ => Err("Usage: rewind <step>".to_string()),
+            },
+            "b" | "break" => self.add_breakpoint(args),
+            "d" | "delete" => match args.first().and_then(|x| x.parse::<usize>().ok()) {
+                Some(n) if n < self.breakpoints.len() => {
+                    let bp = self.breakpoints.remove(n);
+                    Ok(format!("Deleted breakpoint {} ({})", n, bp))
+                },
+                _ => Err("Usage: delete <n>, see info".to_string()),
+            },
+            "i" | "info" => Ok(self.breakpoints.iter()
+                .enumerate()
+                .map(|(i, bp)| format!("{}: {}", i, bp))
+                .collect::<Vec<String>>()
+                .join("\n")),
+            "p" | "print" => Ok(self.registers()),
+            "h" | "history" => {
+                let n = args.first().and_then(|x| x.parse::<usize>().ok()).unwrap_or(10);
+                let history = self.vm.get_history();
+                let start = history.len().saturating_sub(n);
+                Ok(format!("{:?}", &history[start..]))
+            },
+            "l" | "list" => {
+                let pc = args.first().and_then(|x| x.parse::<i32>().ok()).unwrap_or(self.vm.pc);
+                Ok(self.listing(pc, 5))
+            },
+            "patch" => self.patch(args),
+            "profile" => self.profile(args),
+            "boot" => match args.first() {
+                Some(&"on") => {
+                    self.boot = true;
+                    Ok("Boot mode on: continue stops at the first repeated instruction".to_string())
+                },
+                Some(&"off") => {
+                    self.boot = false;
+                    Ok("Boot mode off".to_string())
+                },
+                _ => Err("Usage: boot on|off".to_string()),
+            },
+            "reset" => {
+                self.vm.reset();
+                Ok(self.registers())
+            },
+            "q" | "quit" | "exit" => return None,
+            "help" | "?" => Ok(HELP.to_string()),
+            _ => Err(format!("Unknown command `{}`; try help", cmd)),
+        };
+
+        Some(out.unwrap_or_else(|e| e))
+    }
+
+    /// Read commands until quit or end of input
+    pub fn repl<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        write!(output, "(vm) ")?;
+        output.flush()?;
+        for line in input.lines() {
+            match self.command(&line?) {
+                Some(text) => {
+                    if !text.is_empty() {
+                        writeln!(output, "{}", text)?;
+                    }
+                },
+                None => return Ok(()),
+            }
+            write!(output, "(vm) ")?;
+            output.flush()?;
+        }
+        writeln!(output)
+    }
+}
+
+/// Entry point for the vm-debug subcommand
+pub fn run_debugger(lines: Vec<String>, boot: bool) -> Result<(), String> {
+    let vm = Vm::from_source(lines).map_err(|e| e.to_string())?;
+    let mut debugger = Debugger::new(vm).boot_mode(boot);
+    let stdin = io::stdin();
+    debugger.repl(stdin.lock(), io::stdout()).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::day8_example;
+
+    fn example() -> Debugger {
+        Debugger::new(day8_example())
+    }
+
+    #[test]
+    fn test_debugger_step_and_print() {
+        let mut dbg = example();
+
+        assert_eq!(dbg.command("step 2").unwrap(), "pc = 2, acc = 1, steps = 2\n=>     2: jmp +4");
+        assert_eq!(dbg.command("print").unwrap(), "pc = 2, acc = 1, steps = 2");
+        assert_eq!(dbg.command("history").unwrap(), "[0, 1]");
+        assert_eq!(dbg.command("reset").unwrap(), "pc = 0, acc = 0, steps = 0");
+        assert_eq!(dbg.command("step x").unwrap(), "Usage: step [n]");
+        assert_eq!(dbg.command("frobnicate").unwrap(), "Unknown command `frobnicate`; try help");
+        assert_eq!(dbg.command("quit"), None);
+    }
+
+    #[test]
+    fn test_debugger_breakpoints() {
+        let mut dbg = example();
+
+        assert_eq!(dbg.command("break 4").unwrap(), "Breakpoint 0 (pc 4)");
+        assert!(dbg.command("continue").unwrap().starts_with("Breakpoint 0 (pc 4)\npc = 4, acc = 5"));
+
+        assert_eq!(dbg.command("delete 0").unwrap(), "Deleted breakpoint 0 (pc 4)");
+        assert_eq!(dbg.command("continue 100").unwrap(), "Step limit reached\npc = 7, acc = 87, steps = 106");
+        dbg.command("reset");
+        assert_eq!(dbg.command("boot on").unwrap(), "Boot mode on: continue stops at the first repeated instruction");
+        assert_eq!(dbg.command("continue").unwrap(), "Infinite loop: pc 1 is about to run again; acc = 5");
+        dbg.command("boot off");
+
+        dbg.command("reset");
+        dbg.command("break op acc");
+        dbg.command("break acc >= 4");
+        assert_eq!(dbg.command("info").unwrap(), "0: op acc\n1: acc >= 4");
+        assert!(dbg.command("continue").unwrap().starts_with("Breakpoint 0 (op acc)\npc = 1, acc = 0"));
+        assert!(dbg.command("continue").unwrap().starts_with("Breakpoint 0 (op acc)\npc = 6, acc = 1"));
+        dbg.command("delete 0");
+        assert!(dbg.command("continue").unwrap().starts_with("Breakpoint 0 (acc >= 4)\npc = 4, acc = 5"));
+
+        assert_eq!(dbg.command("break op div").unwrap(), "Unknown opcode `div`");
+        assert_eq!(dbg.command("break acc ~ 3").unwrap(), "Unknown comparison `~`");
+    }
+
+    #[test]
+    fn test_debugger_counting_loop() {
+        // Coming back round a loop isn't stuck when a register counts down
+        let input = vec!["set b 3".to_string(), "add b -1".to_string(), "jnz b -1".to_string(), "acc +5".to_string()];
+        let mut dbg = Debugger::new(Vm::from_source(input.clone()).unwrap());
+        assert_eq!(dbg.command("continue").unwrap(), "Program terminated; acc = 5");
+        let mut dbg = Debugger::new(Vm::from_source(input).unwrap()).boot_mode(true);
+        assert_eq!(dbg.command("continue").unwrap(), "Infinite loop: pc 1 is about to run again; acc = 0");
+
+        assert_eq!(dbg.command("list 2147483647").unwrap(), "");
+        assert_eq!(dbg.command("list -2147483648").unwrap(), "");
+        assert_eq!(dbg.command("continue x").unwrap(), "Usage: continue [n]");
+    }
+
+    #[test]
+    fn test_debugger_patch() {
+        let mut dbg = example();
+
+        assert_eq!(dbg.command("patch 7 nop -4").unwrap(), "    7: jmp -4 -> nop -4");
+        assert_eq!(dbg.command("continue").unwrap(), "Program terminated; acc = 8");
+        assert_eq!(dbg.command("patch 9 nop +0").unwrap(), "Bad pc `9`");
+        assert_eq!(dbg.command("patch 1 nop").unwrap(), "line 1, column 4: missing operand");
+    }
+
+    #[test]
+    fn test_debugger_repl() {
+        let mut dbg = example();
+        let mut output: Vec<u8> = Vec::new();
+
+        dbg.repl("step\nprint\nquit\nstep\n".as_bytes(), &mut output).unwrap();
+
+        let text = String::from_utf8(output).unwrap();
+        assert_eq!(text, "(vm) pc = 1, acc = 0, steps = 1\n=>     1: acc +1\n(vm) pc = 1, acc = 0, steps = 1\n(vm) ");
+    }
+
+    #[test]
+    fn test_debugger_reverse() {
+        let mut dbg = example();
+        dbg.command("boot on");
+
+        dbg.command("continue");
+        assert_eq!(dbg.command("back 2").unwrap(), "pc = 3, acc = 2, steps = 5\n=>     3: acc +3");
+        assert_eq!(dbg.command("break op acc").unwrap(), "Breakpoint 0 (op acc)");
+        assert!(dbg.command("rcontinue").unwrap().starts_with("Breakpoint 0 (op acc)\npc = 6, acc = 1"));
+        assert_eq!(dbg.command("rcontinue").unwrap().lines().nth(1), Some("pc = 1, acc = 0, steps = 1"));
+        assert_eq!(dbg.command("rcontinue").unwrap(), "At the start of the program\npc = 0, acc = 0, steps = 0");
+        assert_eq!(dbg.command("rewind 3").unwrap(), "Only 0 steps have run");
+
+        dbg.command("step 4");
+        assert_eq!(dbg.command("rewind 1").unwrap(), "pc = 1, acc = 0, steps = 1\n=>     1: acc +1");
+    }
+
+    #[test]
+    fn test_debugger_profile() {
+        let mut dbg = example();
+
+        assert_eq!(dbg.command("profile").unwrap(), "Profiling is off; try profile on");
+        assert_eq!(dbg.command("profile on").unwrap(), "Profiling on");
+        dbg.command("boot on");
+        dbg.command("continue");
+        let report = dbg.command("profile").unwrap();
+        assert!(report.starts_with("; 7 instructions executed\n; by opcode: acc 3, jmp 3, nop 1\n"));
+        assert_eq!(dbg.command("profile off").unwrap(), "Profiling off");
+        assert_eq!(dbg.command("profile csv").unwrap(), "Usage: profile [on|off|csv <file>]");
+    }
+}
@@ -0,0 +1,314 @@
+use std::collections::VecDeque;
+use super::{Instruction, Repair, Vm};
+
+/// A straight run of instructions that is only ever entered at the top
+#[derive(Clone, Debug, PartialEq)]
+pub struct BasicBlock {
+    pub start: usize,
+    /// Last instruction in the block, inclusive
+    pub end: usize,
+    /// Program counters control can go to after the block
+    pub successors: Vec<i32>,
+}
+
+/// Static view of a program's control flow, worked out without running it
+#[derive(Clone, Debug, PartialEq)]
+pub struct ControlFlowGraph {
+    pub blocks: Vec<BasicBlock>,
+    /// Index of the block each instruction belongs to
+    pub block_of: Vec<usize>,
+    /// Whether each instruction can be reached from pc 0
+    pub reachable: Vec<bool>,
+    /// Block-level edges (from, to) that close a loop
+    pub back_edges: Vec<(usize, usize)>,
+}
+
+impl ControlFlowGraph {
+    fn new(code: &[Instruction]) -> ControlFlowGraph {
+        let n = code.len();
+        let in_range = |pc: i32| pc >= 0 && (pc as usize) < n;
+
+        // Leaders: the entry, every jump target, and whatever follows a jump
+        let mut leader = vec![false; n];
+        if n > 0 {
+            leader[0] = true;
+        }
+        for (i, inst) in code.iter().enumerate() {
+            let succ = inst.successors(i as i32, n);
+            if succ != [i as i32 + 1] {
+                for &t in succ.iter().filter(|&&t| in_range(t)) {
+                    leader[t as usize] = true;
+                }
+                if i + 1 < n {
+                    leader[i + 1] = true;
+                }
+            }
+        }
+
+        let mut blocks: Vec<BasicBlock> = Vec::new();
+        let mut block_of = vec![0; n];
+        for i in 0..n {
+            if leader[i] {
+                blocks.push(BasicBlock { start: i, end: i, successors: Vec::new() });
+            }
+            let b = blocks.len() - 1;
+            blocks[b].end = i;
+            block_of[i] = b;
+        }
+        for block in blocks.iter_mut() {
+            block.successors = code[block.end].successors(block.end as i32, n);
+        }
+
+        let mut reachable = vec![false; n];
+        let mut queue = VecDeque::new();
+        if n > 0 {
+            reachable[0] = true;
+            queue.push_back(0);
+        }
+        while let Some(i) = queue.pop_front() {
+            for t in code[i].successors(i as i32, n) {
+                if in_range(t) && !reachable[t as usize] {
+                    reachable[t as usize] = true;
+                    queue.push_back(t as usize);
+                }
+            }
+        }
+
+        let mut cfg = ControlFlowGraph { blocks, block_of, reachable, back_edges: Vec::new() };
+        cfg.back_edges = cfg.find_back_edges();
+        cfg
+    }
+
+    /// Block successors that are actually inside the program
+    fn block_successors(&self, b: usize) -> Vec<usize> {
+        let n = self.block_of.len() as i32;
+        self.blocks[b].successors.iter()
+            .filter(|&&t| t >= 0 && t < n)
+            .map(|&t| self.block_of[t as usize])
+            .collect()
+    }
+
+    /// Depth-first from the entry; an edge to a block still on the stack
+    ///  closes a loop.
+    fn find_back_edges(&self) -> Vec<(usize, usize)> {
+        let mut back_edges = Vec::new();
+        if self.blocks.is_empty() {
+            return back_edges;
+        }
+        // 0 = unvisited, 1 = on the stack, 2 = done
+        let mut state = vec![0u8; self.blocks.len()];
+        let mut stack = vec![(0, 0)];
+        state[0] = 1;
+
+        while let Some(&mut (b, ref mut next)) = stack.last_mut() {
+            let succ = self.block_successors(b);
+            if *next < succ.len() {
+                let s = succ[*next];
+                *next += 1;
+                match state[s] {
+                    0 => {
+                        state[s] = 1;
+                        stack.push((s, 0));
+                    },
+                    1 => back_edges.push((b, s)),
+                    _ => {}
+                }
+            } else {
+                state[b] = 2;
+                stack.pop();
+            }
+        }
+
+        back_edges
+    }
+
+    fn is_back_edge(&self, from: usize, to: usize) -> bool {
+        self.back_edges.contains(&(from, to))
+    }
+
+    /// Blocks making up the loop closed by the back edge from -> header:
+    ///  the header plus every reachable block that reaches `from` without
+    ///  going through the header.  Sorted.
+    pub fn natural_loop(&self, from: usize, header: usize) -> Vec<usize> {
+        let mut predecessors: Vec<Vec<usize>> = vec![Vec::new(); self.blocks.len()];
+        for b in (0..self.blocks.len()).filter(|&b| self.reachable[self.blocks[b].start]) {
+            for s in self.block_successors(b) {
+                predecessors[s].push(b);
+            }
+        }
+
+        let mut in_loop = vec![false; self.blocks.len()];
+        in_loop[header] = true;
+        let mut stack = Vec::new();
+        if !in_loop[from] {
+            in_loop[from] = true;
+            stack.push(from);
+        }
+        while let Some(b) = stack.pop() {
+            for &p in &predecessors[b] {
+                if !in_loop[p] {
+                    in_loop[p] = true;
+                    stack.push(p);
+                }
+            }
+        }
+
+        (0..self.blocks.len()).filter(|&b| in_loop[b]).collect()
+    }
+}
+
+impl Vm {
+    #[allow(dead_code)]
+    pub fn control_flow_graph(&self) -> ControlFlowGraph {
+        ControlFlowGraph::new(&self.code)
+    }
+
+    /// Listing of the program split into basic blocks, with jump targets
+    ///  resolved and unreachable code and loop back-edges called out
+    #[allow(dead_code)]
+    pub fn disassemble(&self) -> String {
+        let cfg = self.control_flow_graph();
+        let n = self.code.len() as i32;
+        let mut out = String::new();
+
+        for (b, block) in cfg.blocks.iter().enumerate() {
+            let reachable = if cfg.reachable[block.start] { "" } else { ", unreachable" };
+            out.push_str(&format!("; block {}: {}..={}{}\n", b, block.start, block.end, reachable));
+
+            for pc in block.start..=block.end {
+                let inst = self.code[pc];
+                let mut notes = Vec::new();
+                for t in inst.successors(pc as i32, self.code.len()) {
+                    if t == pc as i32 + 1 {
+                        continue;
+                    }
+                    if t == n {
+                        notes.push("-> end".to_string());
+                    } else if t < 0 || t > n {
+                        notes.push(format!("-> {} (out of bounds)", t));
+                    } else if pc == block.end && cfg.is_back_edge(b, cfg.block_of[t as usize]) {
+                        notes.push(format!("-> {} (loop)", t));
+                    } else {
+                        notes.push(format!("-> {}", t));
+                    }
+                }
+
+                let text = format!("{:>5}: {}", pc, inst);
+                if notes.is_empty() {
+                    out.push_str(&format!("{}\n", text));
+                } else {
+                    out.push_str(&format!("{:<20}; {}\n", text, notes.join(", ")));
+                }
+            }
+        }
+
+        out
+    }
+
+    /// GraphViz DOT of the basic blocks.  Unreachable blocks are greyed out
+    ///  and loop back-edges drawn in blue.  If a repair is given, the edge
+    ///  it adds is drawn in red.
+    #[allow(dead_code)]
+    pub fn cfg_to_dot(&self, repair: Option<&Repair>) -> String {
+        let cfg = self.control_flow_graph();
+        let n = self.code.len() as i32;
+        let node = |t: i32| -> String {
+            if t == n {
+                "end".to_string()
+            } else if t < 0 || t > n {
+                "out_of_bounds".to_string()
+            } else {
+                format!("b{}", cfg.block_of[t as usize])
+            }
+        };
+
+        let mut dot = String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+        dot.push_str("    end [shape=doublecircle, label=\"end\"];\n");
+
+        let mut needs_oob = false;
+        for (b, block) in cfg.blocks.iter().enumerate() {
+            let body: String = (block.start..=block.end)
+                .map(|pc| format!("{}: {}\\l", pc, self.code[pc]))
+                .collect();
+            let style = if cfg.reachable[block.start] { "" } else { ", style=dashed, color=gray, fontcolor=gray" };
+            dot.push_str(&format!("    b{} [label=\"{}\"{}];\n", b, body, style));
+        }
+
+        for (b, block) in cfg.blocks.iter().enumerate() {
+            for &t in &block.successors {
+                needs_oob |= t < 0 || t > n;
+                let is_back = t >= 0 && t < n && cfg.is_back_edge(b, cfg.block_of[t as usize]);
+                let style = if is_back { " [color=blue]" } else { "" };
+                dot.push_str(&format!("    b{} -> {}{};\n", b, node(t), style));
+            }
+        }
+
+        if let Some(fix) = repair {
+            let patched = self.code[fix.pc].with_operator(fix.to);
+            for t in patched.successors(fix.pc as i32, self.code.len()) {
+                needs_oob |= t < 0 || t > n;
+                dot.push_str(&format!("    b{} -> {} [color=red, penwidth=2, label=\"{}: {} -> {}\"];\n",
+                    cfg.block_of[fix.pc], node(t), fix.pc, fix.from, fix.to));
+            }
+        }
+
+        if needs_oob {
+            dot.push_str("    out_of_bounds [shape=octagon, label=\"out of bounds\"];\n");
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::day8_example;
+
+    #[test]
+    fn test_control_flow_graph() {
+        let cfg = day8_example().control_flow_graph();
+        let ranges: Vec<(usize, usize)> = cfg.blocks.iter().map(|b| (b.start, b.end)).collect();
+
+        assert_eq!(ranges, vec![(0, 0), (1, 2), (3, 4), (5, 5), (6, 7), (8, 8)]);
+        assert_eq!(cfg.block_of, vec![0, 1, 1, 2, 2, 3, 4, 4, 5]);
+        assert_eq!(cfg.reachable, vec![true, true, true, true, true, false, true, true, false]);
+        assert_eq!(cfg.back_edges, vec![(2, 1)]);
+        assert_eq!(cfg.blocks[4].successors, vec![3]);
+        assert_eq!(cfg.natural_loop(2, 1), vec![1, 2, 4]);
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let listing = day8_example().disassemble();
+        let lines: Vec<&str> = listing.lines().collect();
+
+        assert_eq!(lines[0], "; block 0: 0..=0");
+        assert_eq!(lines[1], "    0: nop +0");
+        assert_eq!(lines[4], "    2: jmp +4       ; -> 6");
+        assert_eq!(lines[7], "    4: jmp -3       ; -> 1 (loop)");
+        assert_eq!(lines[8], "; block 3: 5..=5, unreachable");
+
+        let vm = Vm::from_source(vec!["jmp +2".to_string(), "jmp -7".to_string()]).unwrap();
+        let listing = vm.disassemble();
+        assert!(listing.contains("    0: jmp +2       ; -> end"));
+        assert!(listing.contains("    1: jmp -7       ; -> -6 (out of bounds)"));
+    }
+
+    #[test]
+    fn test_cfg_to_dot() {
+        let vm = day8_example();
+        let dot = vm.cfg_to_dot(None);
+
+        assert!(dot.starts_with("digraph cfg {\n"));
+        assert!(dot.contains("    b1 [label=\"1: acc +1\\l2: jmp +4\\l\"];\n"));
+        assert!(dot.contains("    b3 [label=\"5: acc -99\\l\", style=dashed, color=gray, fontcolor=gray];\n"));
+        assert!(dot.contains("    b2 -> b1 [color=blue];\n"));
+        assert!(dot.contains("    b5 -> end;\n"));
+        assert!(!dot.contains("out_of_bounds"));
+
+        let fix = vm.repair().unwrap();
+        let dot = vm.cfg_to_dot(Some(&fix));
+        assert!(dot.contains("    b4 -> b5 [color=red, penwidth=2, label=\"7: jmp -> nop\"];\n"));
+    }
+}
@@ -0,0 +1,271 @@
+use super::{Halt, Vm};
+
+/// Small xorshift generator, so generated programs can be reproduced from
+///  a seed without pulling in a crate for it
+pub struct Rng(u64);
+
+#[allow(dead_code)]
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // Zero is a fixed point of xorshift
+        Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Uniform in lo..=hi
+    pub fn range(&mut self, lo: i32, hi: i32) -> i32 {
+        lo + (self.next_u64() % (hi - lo + 1) as u64) as i32
+    }
+
+    /// True with the given chance in percent
+    pub fn chance(&mut self, percent: u32) -> bool {
+        self.next_u64() % 100 < percent as u64
+    }
+
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = (self.next_u64() % (i as u64 + 1)) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+/// Shape of the random boot code to make
+#[derive(Clone, Copy, Debug)]
+pub struct GeneratorConfig {
+    pub len: usize,
+    /// Chance in percent that an instruction is a jmp
+    pub jump_percent: u32,
+    /// Chance in percent that a jmp goes backwards, i.e. can close a loop
+    pub back_percent: u32,
+    /// Largest accumulator change
+    pub max_acc: i32,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> GeneratorConfig {
+        GeneratorConfig {
+            len: 20,
+            jump_percent: 30,
+            back_percent: 30,
+            max_acc: 50,
+        }
+    }
+}
+
+fn acc_or_nop(rng: &mut Rng, config: &GeneratorConfig) -> String {
+    if rng.chance(70) {
+        format!("acc {:+}", rng.range(-config.max_acc, config.max_acc))
+    } else {
+        format!("nop {:+}", rng.range(-(config.len as i32), config.len as i32))
+    }
+}
+
+/// A jmp from pc that stays inside the program or lands just past it
+fn random_jump(rng: &mut Rng, config: &GeneratorConfig, pc: usize) -> String {
+    let n = config.len as i32;
+    let pc = pc as i32;
+    let target = if pc > 0 && rng.chance(config.back_percent) {
+        rng.range(0, pc - 1)
+    } else if pc < n {
+        rng.range(pc + 1, n)
+    } else {
+        n
+    };
+    format!("jmp {:+}", target - pc)
+}
+
+/// Random valid boot code.  Jumps never leave the program, but may well
+///  loop.
+#[allow(dead_code)]
+pub fn random_program(rng: &mut Rng, config: &GeneratorConfig) -> Vec<String> {
+    (0..config.len)
+        .map(|pc| if rng.chance(config.jump_percent) { random_jump(rng, config, pc) } else { acc_or_nop(rng, config) })
+        .collect()
+}
+
+/// Random programs fixable_program tries before giving up
+const FIXABLE_ATTEMPTS: usize = 1000;
+
+/// Random boot code that loops, and that exactly one flip makes terminate.
+///  Returns the program and the pc of that flip, or None if no such
+///  program turned up in a reasonable number of tries.
+#[allow(dead_code)]
+pub fn fixable_program(rng: &mut Rng, config: &GeneratorConfig) -> Option<(Vec<String>, usize)> {
+    fixable_program_within(rng, config, FIXABLE_ATTEMPTS)
+}
+
+fn fixable_program_within(rng: &mut Rng, config: &GeneratorConfig, attempts: usize) -> Option<(Vec<String>, usize)> {
+    let n = config.len.max(2);
+    for _ in 0..attempts {
+        // Lay out a path from pc 0 through some of the instructions and
+        //  off the end, then fill in the rest at random
+        let mut order: Vec<usize> = (1..n).collect();
+        rng.shuffle(&mut order);
+        let mut path = vec![0];
+        path.extend(order.into_iter().take(rng.range(1, n as i32 - 1) as usize));
+
+        let mut lines: Vec<Option<String>> = vec![None; n];
+        for (i, &pc) in path.iter().enumerate() {
+            let next = path.get(i + 1).copied().unwrap_or(n);
+            lines[pc] = Some(if next == pc + 1 && rng.chance(60) {
+                acc_or_nop(rng, config)
+            } else {
+                format!("jmp {:+}", next as i32 - pc as i32)
+            });
+        }
+        let mut lines: Vec<String> = lines.into_iter()
+            .enumerate()
+            .map(|(pc, line)| line.unwrap_or_else(|| {
+                if rng.chance(config.jump_percent) { random_jump(rng, config, pc) } else { acc_or_nop(rng, config) }
+            }))
+            .collect();
+
+        // Break one jmp or nop on the path
+        let candidates: Vec<usize> = path.iter().copied().filter(|&pc| !lines[pc].starts_with("acc")).collect();
+        if candidates.is_empty() {
+            continue;
+        }
+        let broken = candidates[rng.range(0, candidates.len() as i32 - 1) as usize];
+        lines[broken] = if lines[broken].starts_with("jmp") {
+            lines[broken].replacen("jmp", "nop", 1)
+        } else {
+            lines[broken].replacen("nop", "jmp", 1)
+        };
+
+        let fixes = brute_force_fixes(&lines);
+        if fixes.len() == 1 && fixes[0].0 == broken {
+            return Some((lines, broken));
+        }
+    }
+    None
+}
+
+/// Reference answer for day 8 part 2: try every flip and run the result.
+///  Returns (pc, acc) for each flip that terminates, in pc order.  Empty if
+///  the program already terminates.
+#[allow(dead_code)]
+pub fn brute_force_fixes(lines: &[String]) -> Vec<(usize, i32)> {
+    let mut vm = Vm::from_source(lines.to_vec()).unwrap();
+    if let Halt::Terminated { .. } = vm.run() {
+        return Vec::new();
+    }
+
+    let mut fixes = Vec::new();
+    for pc in 0..lines.len() {
+        let flipped = match &lines[pc][..3] {
+            "jmp" => lines[pc].replacen("jmp", "nop", 1),
+            "nop" => lines[pc].replacen("nop", "jmp", 1),
+            _ => continue,
+        };
+        let mut patched = lines.to_vec();
+        patched[pc] = flipped;
+        if let Halt::Terminated { acc } = Vm::from_source(patched).unwrap().run() {
+            fixes.push((pc, acc));
+        }
+    }
+    fixes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Verdict;
+
+    const PROGRAMS: u64 = 300;
+
+    #[test]
+    fn test_generator_shapes() {
+        let mut rng = Rng::new(1);
+        let config = GeneratorConfig { len: 50, jump_percent: 100, back_percent: 0, max_acc: 5 };
+        let lines = random_program(&mut rng, &config);
+        assert_eq!(lines.len(), 50);
+        assert!(lines.iter().all(|x| x.starts_with("jmp +")));
+        // Forward jumps only, so it has to terminate
+        assert_eq!(Vm::from_source(lines).unwrap().run(), Halt::Terminated { acc: 0 });
+
+        let config = GeneratorConfig { jump_percent: 0, ..GeneratorConfig::default() };
+        assert!(random_program(&mut rng, &config).iter().all(|x| !x.starts_with("jmp")));
+
+        // Same seed, same program
+        let config = GeneratorConfig::default();
+        assert_eq!(random_program(&mut Rng::new(7), &config), random_program(&mut Rng::new(7), &config));
+    }
+
+    #[test]
+    fn test_fixable_programs() {
+        let mut rng = Rng::new(2);
+        for len in 2..30 {
+            let config = GeneratorConfig { len, ..GeneratorConfig::default() };
+            let (lines, broken) = fixable_program(&mut rng, &config).unwrap();
+
+            let mut vm = Vm::from_source(lines.clone()).unwrap();
+            assert!(!matches!(vm.run(), Halt::Terminated { .. }), "{:?}", lines);
+            let fix = vm.repair().unwrap();
+            assert_eq!(fix.pc, broken, "{:?}", lines);
+        }
+
+        // Gives up rather than spinning
+        assert_eq!(fixable_program_within(&mut rng, &GeneratorConfig::default(), 0), None);
+    }
+
+    /// gamegirl, the analyzer and the fast backend all have to agree with
+    ///  running every flip by hand
+    #[test]
+    fn test_differential_against_brute_force() {
+        let mut rng = Rng::new(3);
+        for i in 0..PROGRAMS {
+            let config = GeneratorConfig {
+                len: rng.range(1, 40) as usize,
+                jump_percent: rng.range(10, 60) as u32,
+                back_percent: rng.range(0, 60) as u32,
+                max_acc: 100,
+            };
+            // With few backward jumps a fixable program can take too many
+            //  tries to turn up, so make do with a random one
+            let fixable = if i % 2 == 0 { None } else { fixable_program(&mut rng, &config) };
+            let lines = match fixable {
+                Some((lines, _)) => lines,
+                None => random_program(&mut rng, &config),
+            };
+            let expected = brute_force_fixes(&lines);
+
+            let mut vm = Vm::from_source(lines.clone()).unwrap();
+            let original = vm.run();
+            let analysis = vm.analyze();
+
+            // Part 1 both ways
+            match original {
+                Halt::Terminated { acc } | Halt::InfiniteLoop { acc, .. } => assert_eq!(analysis.acc, Some(acc), "{:?}", lines),
+                _ => {}
+            }
+            vm.reset();
+            let mut fast = vm.compile().unwrap();
+            assert_eq!(fast.run(), vm.run(), "{:?}", lines);
+
+            let found: Vec<(usize, i32)> = analysis.fixes.iter().map(|f| (f.pc, f.acc.unwrap())).collect();
+            assert_eq!(found, expected, "{:?}", lines);
+
+            let gamegirl = vm.execute_gamegirl();
+            match (original, analysis.verdict) {
+                (Halt::Terminated { acc }, Verdict::Terminates) => assert_eq!(gamegirl, Halt::Terminated { acc }),
+                (_, Verdict::NeedsFlip) => {
+                    // gamegirl takes the first flip the program runs into,
+                    //  which is one of the right answers
+                    match gamegirl {
+                        Halt::Terminated { acc } => assert!(expected.iter().any(|&(_, a)| a == acc), "{:?}", lines),
+                        other => panic!("{:?} for {:?}", other, lines),
+                    }
+                },
+                (_, Verdict::Loops) => assert!(expected.is_empty() && !matches!(gamegirl, Halt::Terminated { .. })),
+                other => panic!("{:?} for {:?}", other, lines),
+            }
+        }
+    }
+}
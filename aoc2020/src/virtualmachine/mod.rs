@@ -1,8 +1,50 @@
 #[allow(dead_code)]
+use std::collections::VecDeque;
+use std::fmt;
 use std::str::FromStr;
-use std::num::ParseIntError;
 use log::{trace, debug, info, warn, error}; // trace, debug, info, warn, error
 
+mod parse;
+mod repair;
+mod debugger;
+mod disasm;
+mod opcodes;
+mod channel;
+mod scheduler;
+mod snapshot;
+mod profile;
+mod analysis;
+mod compiled;
+mod generate;
+mod search;
+
+#[allow(unused_imports)]
+pub use parse::{ParseError, ParseErrorKind, ParseMode};
+#[allow(unused_imports)]
+pub use repair::{Repair, RepairError};
+#[allow(unused_imports)]
+pub use debugger::{Breakpoint, Comparison, Debugger, run_debugger};
+#[allow(unused_imports)]
+pub use disasm::{BasicBlock, ControlFlowGraph};
+#[allow(unused_imports)]
+pub use opcodes::{Context, Control, Flow, InstructionSet, Opcode, Operand, OperandKind, REGISTERS};
+#[allow(unused_imports)]
+pub use channel::{Channel, Input, Output};
+#[allow(unused_imports)]
+pub use scheduler::{Schedule, Scheduler, Status};
+#[allow(unused_imports)]
+pub use snapshot::Snapshot;
+#[allow(unused_imports)]
+pub use profile::{HotLoop, Profile, ProfileReport};
+#[allow(unused_imports)]
+pub use analysis::{Analysis, Fix, Verdict};
+#[allow(unused_imports)]
+pub use compiled::CompiledVm;
+#[allow(unused_imports)]
+pub use generate::{brute_force_fixes, fixable_program, random_program, GeneratorConfig, Rng};
+#[allow(unused_imports)]
+pub use search::{Edit, Goal, Mutation, SearchOptions};
+
 #[allow(dead_code)]
 fn _get_rid_of_log_unused_import_warnings() {
     trace!("Example trace.");
@@ -12,174 +54,506 @@ fn _get_rid_of_log_unused_import_warnings() {
     error!("Example error.");
 }
 
-#[derive(PartialEq, Copy, Clone, Debug)]
-enum InstructionCode {
-    NOP,
-    ACC,
-    JMP,
+/// An opcode from the opcode table.  Compares by mnemonic.
+#[derive(Copy, Clone)]
+pub struct InstructionCode(&'static Opcode);
+
+#[allow(dead_code)]
+impl InstructionCode {
+    pub const NOP: InstructionCode = InstructionCode(&opcodes::NOP);
+    pub const ACC: InstructionCode = InstructionCode(&opcodes::ACC);
+    pub const JMP: InstructionCode = InstructionCode(&opcodes::JMP);
+    pub const SET: InstructionCode = InstructionCode(&opcodes::SET);
+    pub const ADD: InstructionCode = InstructionCode(&opcodes::ADD);
+    pub const MUL: InstructionCode = InstructionCode(&opcodes::MUL);
+    pub const JNZ: InstructionCode = InstructionCode(&opcodes::JNZ);
+    pub const JZ: InstructionCode = InstructionCode(&opcodes::JZ);
+    pub const HLT: InstructionCode = InstructionCode(&opcodes::HLT);
+    pub const IN: InstructionCode = InstructionCode(&opcodes::IN);
+    pub const OUT: InstructionCode = InstructionCode(&opcodes::OUT);
+
+    pub fn opcode(&self) -> &'static Opcode {
+        self.0
+    }
+
+    /// The jmp/nop swap used to repair boot code
+    pub fn flipped(&self) -> Option<InstructionCode> {
+        self.0.flip.map(InstructionCode)
+    }
+}
+
+/// Compares by table entry, so an opcode registered under a built-in
+///  mnemonic in another set isn't mistaken for the built-in
+impl PartialEq for InstructionCode {
+    fn eq(&self, other: &InstructionCode) -> bool {
+        std::ptr::eq(self.0, other.0)
+    }
+}
+
+impl fmt::Debug for InstructionCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.mnemonic.to_uppercase())
+    }
+}
+
+impl fmt::Display for InstructionCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.mnemonic)
+    }
+}
+
+/// Where a jump by offset from pc lands.  A jump that overflows would land
+///  out of bounds anyway, so it goes to the nearest end of the range.
+fn jump_target(pc: i32, offset: i32) -> i32 {
+    pc.checked_add(offset).unwrap_or(if offset < 0 { i32::MIN } else { i32::MAX })
 }
 
-#[derive(Debug, Copy, Clone)]
+/// Opcodes take at most this many operands
+const MAX_OPERANDS: usize = 2;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 struct Instruction {
     operator: InstructionCode,
-    operand: i32
+    operands: [Operand; MAX_OPERANDS],
 }
 
 impl Instruction {
+    fn new(operator: InstructionCode, args: &[Operand]) -> Instruction {
+        let mut operands = [Operand::Imm(0); MAX_OPERANDS];
+        operands[..args.len()].copy_from_slice(args);
+        Instruction { operator, operands }
+    }
+
+    /// What a line that can't be read turns into
+    fn nop() -> Instruction {
+        Instruction::new(InstructionCode::NOP, &[Operand::Imm(0)])
+    }
+
+    /// Lenient parse of a single line; anything unreadable becomes `nop +0`
+    #[allow(dead_code)]
     pub fn parse_string(input_str: String) -> Instruction {
-        let parts: Vec<String> = input_str.split(' ').map(|x| x.to_string()).collect(); 
-        let op = match &parts[0][..] {
-            "nop" => InstructionCode::NOP,
-            "acc" => InstructionCode::ACC,
-            "jmp" => InstructionCode::JMP,
-            _ => InstructionCode::NOP,
+        parse::parse_line(&InstructionSet::standard(), &input_str, 1).unwrap_or_else(|_| Instruction::nop())
+    }
+
+    pub fn operands(&self) -> &[Operand] {
+        &self.operands[..self.operator.0.operands.len()]
+    }
+
+    /// The i'th operand if it's a number
+    #[allow(dead_code)]
+    pub fn operand(&self, i: usize) -> Option<i32> {
+        match self.operands().get(i) {
+            Some(Operand::Imm(x)) => Some(*x),
+            _ => None,
+        }
+    }
+
+    /// The same operands under another opcode with the same operand shape
+    pub fn with_operator(&self, operator: InstructionCode) -> Instruction {
+        Instruction { operator, operands: self.operands }
+    }
+
+    /// Where the program counter can go after running this instruction at
+    ///  pc, in a program `len` instructions long
+    pub fn successors(&self, pc: i32, len: usize) -> Vec<i32> {
+        let target = || match self.operands().last() {
+            Some(Operand::Imm(x)) => jump_target(pc, *x),
+            _ => pc + 1,
         };
+        match self.operator.0.flow {
+            Flow::Next => vec![pc + 1],
+            Flow::Jump => vec![target()],
+            Flow::Branch if target() == pc + 1 => vec![pc + 1],
+            Flow::Branch => vec![pc + 1, target()],
+            Flow::Stop => vec![len as i32],
+        }
+    }
+}
 
-        let opa = parts[1].parse::<i32>();
-        match opa {
-            Ok(a) => {
-                Instruction {
-                    operator: op,
-                    operand: a
-                }
-            },
-            Err(_) => {
-                Instruction {
-                    operator: InstructionCode::NOP,
-                    operand: 0,
-                }
-            }
+/// Formats the same way boot code is written, e.g. `jmp -4`
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.operator)?;
+        for operand in self.operands() {
+            write!(f, " {}", operand)?;
         }
+        Ok(())
     }
 }
 
 impl FromStr for Instruction {
-    type Err = ParseIntError;
+    type Err = ParseError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let parts: Vec<String> = s.split(' ').map(|x| x.to_string()).collect(); 
-        let op = match &parts[0][..] {
-            "nop" => InstructionCode::NOP,
-            "acc" => InstructionCode::ACC,
-            "jmp" => InstructionCode::JMP,
-            _ => InstructionCode::NOP,
-        };
-
-        let opa = parts[1].parse::<i32>()?;
-        Ok(Instruction {
-            operator: op,
-            operand: opa,
-        })
+        parse::parse_line(&InstructionSet::standard(), s, 1)
     }
 }
 
+/// Why a run of the VM stopped
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Halt {
+    /// The program counter landed just past the last instruction
+    Terminated { acc: i32 },
+    /// The instruction at `pc` was about to run a second time
+    InfiniteLoop { pc: i32, acc: i32 },
+    /// The program counter landed somewhere other than an instruction
+    OutOfBounds { pc: i32 },
+    /// The instruction at `pc` wants input and there is none
+    Blocked { pc: i32 },
+    /// Ran out of steps before anything else happened
+    StepLimit,
+}
+
+/// What one executed instruction did, which is enough to undo it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    /// Program counter the instruction ran at
+    pub pc: i32,
+    /// Registers it wrote, with the values they held before
+    pub writes: Vec<(usize, i32)>,
+    /// Input value it consumed
+    pub input: Option<i32>,
+    /// Whether it produced a value of output
+    pub output: bool,
+}
+
 pub struct Vm {
     code: Vec<Instruction>,
+    isa: InstructionSet,
     pc: i32,
-    accumulator: i32,
-    history: Vec<i32>,
+    registers: Vec<i32>,
+    input: VecDeque<i32>,
+    source: Option<Box<dyn Input>>,
+    output: Vec<i32>,
+    sink: Option<Box<dyn Output>>,
+    /// Only kept once record_history has been called, since it grows with
+    ///  every instruction run
+    history: Option<Vec<Step>>,
+    steps: usize,
+    /// Input values read since the last reset, for reset to queue again
+    read: Vec<i32>,
+    visits: Vec<u32>,
+    profile: Option<Profile>,
 }
 
 impl Vm {
+    /// Load a program leniently; see ParseMode
     #[allow(dead_code)]
     pub fn new(lines: Vec<String>) -> Vm {
-        let code = lines.iter().map(|x| Instruction::parse_string(x.clone())).collect();
+        Vm::from_source_with_mode(lines, ParseMode::Lenient).unwrap()
+    }
+
+    /// Load a program, failing on the first line that doesn't parse
+    #[allow(dead_code)]
+    pub fn from_source(lines: Vec<String>) -> Result<Vm, ParseError> {
+        Vm::from_source_with_mode(lines, ParseMode::Strict)
+    }
+
+    pub fn from_source_with_mode(lines: Vec<String>, mode: ParseMode) -> Result<Vm, ParseError> {
+        Vm::from_source_with_set(lines, mode, InstructionSet::standard())
+    }
+
+    /// Load a program written for a particular instruction set
+    pub fn from_source_with_set(lines: Vec<String>, mode: ParseMode, isa: InstructionSet) -> Result<Vm, ParseError> {
+        let code = parse::parse_program(&isa, &lines, mode)?;
+        let mut vm = Vm::from_code(code);
+        vm.isa = isa;
+        Ok(vm)
+    }
+
+    fn from_code(code: Vec<Instruction>) -> Vm {
+        let visits = vec![0; code.len()];
 
         Vm {
-            code: code,
+            code,
+            isa: InstructionSet::standard(),
             pc: 0,
-            accumulator: 0,
-            history: Vec::new(),
+            registers: vec![0; REGISTERS.len()],
+            input: VecDeque::new(),
+            source: None,
+            output: Vec::new(),
+            sink: None,
+            history: None,
+            steps: 0,
+            read: Vec::new(),
+            visits,
+            profile: None,
         }
     }
 
+    /// Keep what every instruction does from now on, so it can be undone
     #[allow(dead_code)]
-    pub fn execute_once(&mut self) {
-        let inst = &self.code[self.pc.clone() as usize];
+    pub fn record_history(&mut self) {
+        if self.history.is_none() {
+            self.history = Some(Vec::new());
+        }
+    }
+
+    /// Execute the instruction at the program counter.  If the program
+    ///  counter doesn't point at an instruction, or the instruction can't
+    ///  run yet, nothing happens and the reason is returned instead.
+    #[allow(dead_code)]
+    pub fn execute_once(&mut self) -> Option<Halt> {
+        if self.pc == self.code.len() as i32 {
+            return Some(Halt::Terminated { acc: self.get_acc() });
+        } else if self.pc < 0 || self.pc > self.code.len() as i32 {
+            return Some(Halt::OutOfBounds { pc: self.pc });
+        }
+
+        let inst = self.code[self.pc as usize];
+        let old_pc = self.pc;
 
         trace!("{:?}", inst);
         trace!("Old PC: {}", self.pc);
 
-        self.history.push(self.pc);
+        let mut ctx = Context {
+            registers: &mut self.registers,
+            input: &mut self.input,
+            source: &mut self.source,
+            output: &mut self.output,
+            sink: &mut self.sink,
+            writes: Vec::new(),
+            consumed: None,
+            produced: false,
+        };
+        let control = (inst.operator.0.exec)(&mut ctx, inst.operands());
+        let step = Step { pc: old_pc, writes: ctx.writes, input: ctx.consumed, output: ctx.produced };
+
+        self.pc = match control {
+            Control::Next => self.pc + 1,
+            Control::Jump(offset) => jump_target(self.pc, offset),
+            Control::Halt => self.code.len() as i32,
+            Control::Block => return Some(Halt::Blocked { pc: self.pc }),
+        };
 
-        match inst.operator {
-            InstructionCode::NOP => {
-                self.pc += 1;
-            },
-            InstructionCode::ACC => {
-                self.accumulator += inst.operand;
-                self.pc += 1;
+        trace!("New PC: {}", self.pc);
+
+        if let Some(value) = step.input {
+            self.read.push(value);
+        }
+        if let Some(history) = self.history.as_mut() {
+            history.push(step);
+        }
+        self.steps += 1;
+        self.visits[old_pc as usize] += 1;
+        if let Some(profile) = self.profile.as_mut() {
+            profile.hit(old_pc as usize);
+        }
+        None
+    }
+
+    /// Undo the last executed instruction.  Returns false if there is
+    ///  nothing left to undo, which is always the case unless history is
+    ///  being recorded.
+    #[allow(dead_code)]
+    pub fn step_back(&mut self) -> bool {
+        match self.history.as_mut().and_then(|history| history.pop()) {
+            Some(step) => {
+                trace!("Stepping back from {} to {}", self.pc, step.pc);
+                self.pc = step.pc;
+                for &(reg, old) in step.writes.iter().rev() {
+                    self.registers[reg] = old;
+                }
+                if let Some(value) = step.input {
+                    self.read.pop();
+                    self.input.push_front(value);
+                }
+                if step.output {
+                    self.output.pop();
+                    if let Some(sink) = self.sink.as_mut() {
+                        if !sink.retract() {
+                            warn!("Value sent from pc {} was already read; it can't be taken back", step.pc);
+                        }
+                    }
+                }
+                self.visits[step.pc as usize] -= 1;
+                self.steps -= 1;
+                true
             },
-            InstructionCode::JMP => {
-                self.pc += inst.operand;
-            }
+            None => false,
         }
+    }
 
-        trace!("New PC: {}", self.pc);
+    /// Undo instructions until only the first `step` of them have run.
+    ///  Returns false if fewer than that have run, or if the history doesn't
+    ///  go back that far.
+    #[allow(dead_code)]
+    pub fn rewind_to(&mut self, step: usize) -> bool {
+        let recorded = self.history.as_ref().map_or(0, |history| history.len());
+        if step > self.steps || self.steps - step > recorded {
+            return false;
+        }
+        while self.steps > step {
+            self.step_back();
+        }
+        true
+    }
+
+    /// Has the instruction at pc run since the last reset?
+    pub fn has_visited(&self, pc: i32) -> bool {
+        pc >= 0 && (pc as usize) < self.code.len() && self.visits[pc as usize] > 0
     }
 
+    /// Number of instructions run since the last reset
+    #[allow(dead_code)]
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// Back to the start: registers cleared, input read since the last
+    ///  reset queued again and output taken back.  Works whether or not
+    ///  history is being recorded.
     pub fn reset(&mut self) {
         self.pc = 0;
-        self.accumulator = 0;
-        self.history.clear();
+        for r in self.registers.iter_mut() {
+            *r = 0;
+        }
+        for value in self.read.drain(..).rev() {
+            self.input.push_front(value);
+        }
+        if let Some(sink) = self.sink.as_mut() {
+            if !(0..self.output.len()).all(|_| sink.retract()) {
+                warn!("Some output was already read; it can't be taken back");
+            }
+        }
+        self.output.clear();
+        self.steps = 0;
+        self.visits = vec![0; self.code.len()];
+        if let Some(history) = self.history.as_mut() {
+            history.clear();
+        }
     }
 
+    /// Run from the current state until the program stops, or an
+    ///  instruction is about to run a second time
+    pub fn run(&mut self) -> Halt {
+        self.run_with_limit(usize::MAX)
+    }
+
+    /// As run, but give up after `max_steps` instructions
+    pub fn run_with_limit(&mut self, max_steps: usize) -> Halt {
+        for _ in 0..max_steps {
+            if self.has_visited(self.pc) {
+                return Halt::InfiniteLoop { pc: self.pc, acc: self.get_acc() };
+            }
+            if let Some(halt) = self.execute_once() {
+                return halt;
+            }
+        }
+        Halt::StepLimit
+    }
+
+    /// Run without loop detection, for programs that are meant to go
+    ///  round a loop more than once.  Gives up after `max_steps`.
     #[allow(dead_code)]
-    pub fn execute_until_repeat(&mut self) {
-        while !self.history.contains(&self.pc) {
-            self.execute_once();
+    pub fn run_until_halt(&mut self, max_steps: usize) -> Halt {
+        for _ in 0..max_steps {
+            if let Some(halt) = self.execute_once() {
+                return halt;
+            }
         }
+        Halt::StepLimit
     }
 
     #[allow(dead_code)]
-    pub fn execute_gamegirl(&mut self) {
-        let backup = self.code.clone();
-        let mut investigator: i32 = -1;
-
-        while self.pc < self.code.len() as i32 {
-            self.execute_once();
-
-            trace!("History: {:?}", self.history);
-
-            if self.history.contains(&self.pc) {
-                // Try auto correct
-                self.code.copy_from_slice(&backup[..]);
-                self.reset();
-                'investigation: loop {
-                    investigator += 1;
-                    if investigator >= self.code.len() as i32 {
-                        break 'investigation;
-                    }
-                    match self.code[investigator as usize].operator {
-                        InstructionCode::JMP => {
-                            trace!("Trying to auto-correct {}", investigator);
-                            self.code[investigator as usize].operator = InstructionCode::NOP;
-                            break 'investigation;
-                        },
-                        InstructionCode::NOP => {
-                            trace!("Trying to auto-correct {}", investigator);
-                            self.code[investigator as usize].operator = InstructionCode::JMP;
-                            break 'investigation;
-                        },
-                        _ => {}
-                    }
-                }
+    pub fn execute_until_repeat(&mut self) -> Halt {
+        self.run()
+    }
 
-            }
+    /// Run the program from the start, first applying the jmp/nop flip
+    ///  that makes it terminate if it needs one.  The flip is left in place.
+    ///  If no flip works it runs unchanged, to the loop.
+    #[allow(dead_code)]
+    pub fn execute_gamegirl(&mut self) -> Halt {
+        match self.repair() {
+            Ok(fix) => {
+                trace!("Auto-correcting {}: {:?} -> {:?}", fix.pc, fix.from, fix.to);
+                self.code[fix.pc] = self.code[fix.pc].with_operator(fix.to);
+            },
+            Err(RepairError::AlreadyTerminates) => {},
+            Err(e) => warn!("Can't auto-correct: {}", e),
         }
+        self.reset();
+        self.run()
     }
 
     #[allow(dead_code)]
     #[allow(dead_code)]
     pub fn get_acc(&self) -> i32 {
-        self.accumulator
+        self.registers[0]
     }
 
+    /// Value of a register by name, e.g. `b`
+    #[allow(dead_code)]
+    pub fn get_register(&self, name: &str) -> Option<i32> {
+        opcodes::register_index(name).map(|r| self.registers[r])
+    }
+
+    #[allow(dead_code)]
+    pub fn set_register(&mut self, name: &str, value: i32) -> bool {
+        match opcodes::register_index(name) {
+            Some(r) => {
+                self.registers[r] = value;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Queue a value for `in` to read
+    #[allow(dead_code)]
+    pub fn push_input(&mut self, value: i32) {
+        self.input.push_back(value);
+    }
+
+    /// Everything `out` has written so far
+    #[allow(dead_code)]
+    pub fn get_output(&self) -> &[i32] {
+        &self.output
+    }
+
+    /// Where `in` reads from once the VM's own input queue is empty.
+    ///  Values read from here are put on the VM's own queue if the step
+    ///  that read them is undone.
+    #[allow(dead_code)]
+    pub fn set_input<I: Input + 'static>(&mut self, source: I) {
+        self.source = Some(Box::new(source));
+    }
+
+    /// Where `out` sends values, besides the VM's own output record
+    #[allow(dead_code)]
+    pub fn set_output<O: Output + 'static>(&mut self, sink: O) {
+        self.sink = Some(Box::new(sink));
+    }
+
+    #[allow(dead_code)]
+    pub fn get_pc(&self) -> i32 {
+        self.pc
+    }
+
+    /// Program counters executed since record_history was called
     #[allow(dead_code)]
     pub fn get_history(&self) -> Vec<i32> {
-        self.history.clone()
+        self.get_steps().iter().map(|x| x.pc).collect()
+    }
+
+    /// Every instruction executed since record_history was called, along
+    ///  with its effects
+    #[allow(dead_code)]
+    pub fn get_steps(&self) -> &[Step] {
+        self.history.as_deref().unwrap_or(&[])
     }
 }
 
+/// The example program from the day 8 puzzle, which the tests all over
+///  this module run
+#[cfg(test)]
+pub(crate) const DAY8_EXAMPLE: [&str; 9] = ["nop +0", "acc +1", "jmp +4", "acc +3", "jmp -3", "acc -99", "acc +1", "jmp -4", "acc +6"];
+
+#[cfg(test)]
+pub(crate) fn day8_example() -> Vm {
+    Vm::from_source(DAY8_EXAMPLE.iter().map(|x| x.to_string()).collect()).unwrap()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -206,28 +580,16 @@ mod tests {
         assert_eq!(inst1.operator, InstructionCode::NOP);
         assert_eq!(inst2.operator, InstructionCode::JMP);
         assert_eq!(inst3.operator, InstructionCode::ACC);
-        assert_eq!(inst1.operand, 0);
-        assert_eq!(inst2.operand, 4);
-        assert_eq!(inst3.operand, -99);
+        assert_eq!(inst1.operand(0), Some(0));
+        assert_eq!(inst2.operand(0), Some(4));
+        assert_eq!(inst3.operand(0), Some(-99));
     }
 
     #[test]
     fn test_vm_creation_and_run() {
         init();
 
-        let input = vec![
-            "nop +0".to_string(),
-            "acc +1".to_string(),
-            "jmp +4".to_string(),
-            "acc +3".to_string(),
-            "jmp -3".to_string(),
-            "acc -99".to_string(),
-            "acc +1".to_string(),
-            "jmp -4".to_string(),
-            "acc +6".to_string(),
-        ];
-
-        let mut vm = Vm::new(input);
+        let mut vm = Vm::new(DAY8_EXAMPLE.iter().map(|x| x.to_string()).collect());
         // vm.execute_once();
         vm.execute_until_repeat();
 
@@ -238,21 +600,251 @@ mod tests {
     fn test_vm_autocorrect() {
         init();
 
+        let mut vm = Vm::new(DAY8_EXAMPLE.iter().map(|x| x.to_string()).collect());
+        vm.execute_gamegirl();
+
+        assert_eq!(vm.get_acc(), 8);
+    }
+
+    #[test]
+    fn test_instruction_parse_errors() {
+        init();
+
+        let err = "jpm +4".parse::<Instruction>().unwrap_err();
+        assert_eq!(err, ParseError { line: 1, column: 1, token: "jpm".to_string(), kind: ParseErrorKind::UnknownOpcode });
+
+        let err = "acc  +x9".parse::<Instruction>().unwrap_err();
+        assert_eq!(err, ParseError { line: 1, column: 6, token: "+x9".to_string(), kind: ParseErrorKind::BadOperand });
+        assert_eq!(err.to_string(), "line 1, column 6: bad operand `+x9`");
+
+        let err = "nop".parse::<Instruction>().unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::MissingOperand);
+        assert_eq!(err.column, 4);
+
+        let err = "jmp +1 +2".parse::<Instruction>().unwrap_err();
+        assert_eq!((err.column, err.kind), (8, ParseErrorKind::TrailingInput));
+
+        assert_eq!("".parse::<Instruction>().unwrap_err().kind, ParseErrorKind::EmptyLine);
+        assert_eq!(Instruction::parse_string("jpm +4".to_string()).operator, InstructionCode::NOP);
+    }
+
+    #[test]
+    fn test_vm_from_source() {
+        init();
+
         let input = vec![
             "nop +0".to_string(),
             "acc +1".to_string(),
             "jmp +4".to_string(),
-            "acc +3".to_string(),
-            "jmp -3".to_string(),
-            "acc -99".to_string(),
-            "acc +1".to_string(),
-            "jmp -4".to_string(),
-            "acc +6".to_string(),
+            "acc 3".to_string(),
+            "jpm -3".to_string(),
         ];
 
-        let mut vm = Vm::new(input);
-        vm.execute_gamegirl();
+        let err = Vm::from_source(input.clone()).err().unwrap();
+        assert_eq!(err.to_string(), "line 5, column 1: unknown opcode `jpm`");
 
-        assert_eq!(vm.get_acc(), 8);
+        let vm = Vm::from_source_with_mode(input, ParseMode::Lenient).unwrap();
+        assert_eq!(vm.code.len(), 5);
+        assert_eq!(vm.code[3].operand(0), Some(3));
+        assert_eq!(vm.code[4].operator, InstructionCode::NOP);
+    }
+
+    #[test]
+    fn test_vm_halt_reasons() {
+        init();
+
+        let looping = vec!["nop +0".to_string(), "acc +1".to_string(), "jmp -2".to_string()];
+        let mut vm = Vm::from_source(looping.clone()).unwrap();
+        assert_eq!(vm.run(), Halt::InfiniteLoop { pc: 0, acc: 1 });
+
+        vm.reset();
+        assert_eq!(vm.run_with_limit(2), Halt::StepLimit);
+        assert_eq!(vm.get_acc(), 1);
+
+        let mut vm = Vm::from_source(vec!["acc +2".to_string(), "nop +0".to_string()]).unwrap();
+        assert_eq!(vm.run(), Halt::Terminated { acc: 2 });
+
+        let mut vm = Vm::from_source(vec!["acc +2".to_string(), "jmp +5".to_string()]).unwrap();
+        assert_eq!(vm.run(), Halt::OutOfBounds { pc: 6 });
+        assert_eq!(vm.execute_once(), Some(Halt::OutOfBounds { pc: 6 }));
+
+        let mut vm = Vm::from_source(vec!["jmp -1".to_string()]).unwrap();
+        assert_eq!(vm.run(), Halt::OutOfBounds { pc: -1 });
+
+        // Jumps too far to add up are out of bounds too
+        let mut vm = Vm::from_source(vec!["nop +0".to_string(), "jmp +2147483647".to_string()]).unwrap();
+        assert_eq!(vm.run(), Halt::OutOfBounds { pc: i32::MAX });
+        assert_eq!(vm.code[1].successors(1, 2), vec![i32::MAX]);
+        let mut vm = Vm::from_source(vec!["set b 1".to_string(), "jnz b +2147483647".to_string()]).unwrap();
+        assert_eq!(vm.run(), Halt::OutOfBounds { pc: i32::MAX });
+        assert!(vm.disassemble().contains("(out of bounds)"));
+
+        let mut vm = Vm::from_source(looping).unwrap();
+        assert_eq!(vm.execute_gamegirl(), Halt::Terminated { acc: 1 });
+    }
+
+    #[test]
+    fn test_vm_repair() {
+        init();
+
+        let vm = day8_example();
+        let fix = vm.repair().unwrap();
+        assert_eq!(fix, Repair { pc: 7, from: InstructionCode::JMP, to: InstructionCode::NOP, acc: 8 });
+
+        // Nothing to repair in a program that already terminates
+        let vm = Vm::from_source(vec!["acc +1".to_string(), "nop +7".to_string()]).unwrap();
+        assert_eq!(vm.repair(), Err(RepairError::AlreadyTerminates));
+
+        // Stuck whichever instruction gets flipped
+        let mut vm = Vm::from_source(vec!["acc +1".to_string(), "jmp -1".to_string(), "jmp -2".to_string()]).unwrap();
+        assert_eq!(vm.repair(), Err(RepairError::NoFix));
+        assert_eq!(vm.execute_gamegirl(), Halt::InfiniteLoop { pc: 0, acc: 1 });
+
+        // The fix is the nop, not the jmp in front of it
+        let mut vm = Vm::from_source(vec![
+            "nop +3".to_string(),
+            "acc +1".to_string(),
+            "jmp -2".to_string(),
+        ]).unwrap();
+        assert_eq!(vm.repair().map(|x| (x.pc, x.acc)), Ok((0, 0)));
+        assert_eq!(vm.execute_gamegirl(), Halt::Terminated { acc: 0 });
+    }
+
+    #[test]
+    fn test_vm_step_back() {
+        init();
+
+        // Nothing to undo unless asked to keep it, and then only from
+        //  that point on
+        let mut plain = day8_example();
+        plain.run_with_limit(3);
+        assert!(plain.get_history().is_empty() && !plain.step_back());
+        plain.record_history();
+        assert_eq!(plain.run(), Halt::InfiniteLoop { pc: 1, acc: 5 });
+        assert_eq!(plain.get_history(), vec![6, 7, 3, 4]);
+        assert!(!plain.rewind_to(2));
+        assert!(plain.rewind_to(3));
+        assert_eq!((plain.get_pc(), plain.get_acc(), plain.steps()), (6, 1, 3));
+
+        let mut vm = day8_example();
+        vm.record_history();
+        vm.execute_until_repeat();
+        assert_eq!(vm.get_history(), vec![0, 1, 2, 6, 7, 3, 4]);
+        assert_eq!(vm.get_steps()[3], Step { pc: 6, writes: vec![(0, 1)], input: None, output: false });
+
+        assert!(vm.step_back());
+        assert_eq!((vm.get_pc(), vm.get_acc(), vm.steps()), (4, 5, 6));
+        assert!(!vm.has_visited(4));
+
+        assert!(vm.rewind_to(2));
+        assert_eq!((vm.get_pc(), vm.get_acc()), (2, 1));
+        assert!(!vm.rewind_to(3));
+
+        // Going forwards again retraces the same steps
+        assert_eq!(vm.run(), Halt::InfiniteLoop { pc: 1, acc: 5 });
+
+        assert!(vm.rewind_to(0));
+        assert_eq!((vm.get_pc(), vm.get_acc()), (0, 0));
+        assert!(!vm.step_back());
+    }
+
+    fn program(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|x| x.to_string()).collect()
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_extended_instruction_set() {
+        init();
+
+        let factorial = program(&[
+            "in b",
+            "set a 1",
+            "mul a b",
+            "add b -1",
+            "jnz b -2",
+            "out a",
+            "hlt",
+            "acc +100",
+        ]);
+        let mut vm = Vm::from_source(factorial).unwrap();
+        assert_eq!(vm.run(), Halt::Blocked { pc: 0 });
+
+        vm.push_input(5);
+        assert_eq!(vm.run_until_halt(1000), Halt::Terminated { acc: 120 });
+        assert_eq!(vm.get_output(), &[120]);
+        assert_eq!(vm.get_register("b"), Some(0));
+        assert_eq!(vm.get_register("z"), None);
+
+        // Undoing puts the input back and takes the output away
+        vm.reset();
+        assert_eq!((vm.get_acc(), vm.get_output().len()), (0, 0));
+        assert_eq!(vm.run_until_halt(1000), Halt::Terminated { acc: 120 });
+
+        let inst = "jz c +3".parse::<Instruction>().unwrap();
+        assert_eq!(inst.operands(), &[Operand::Reg(2), Operand::Imm(3)]);
+        assert_eq!(inst.to_string(), "jz c +3");
+        assert_eq!(inst.successors(4, 10), vec![5, 7]);
+        assert_eq!("hlt".parse::<Instruction>().unwrap().successors(4, 10), vec![10]);
+
+        let err = "mul 3 a".parse::<Instruction>().unwrap_err();
+        assert_eq!((err.column, err.kind), (5, ParseErrorKind::BadOperand));
+        let err = "hlt +0".parse::<Instruction>().unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::TrailingInput);
+
+        // The original boot code instruction set doesn't know about any of this
+        let err = Vm::from_source_with_set(program(&["set a 1"]), ParseMode::Strict, InstructionSet::boot()).err().unwrap();
+        assert_eq!(err.kind, ParseErrorKind::UnknownOpcode);
+    }
+
+    static DBL: Opcode = Opcode {
+        mnemonic: "dbl",
+        operands: &[OperandKind::Reg],
+        flow: Flow::Next,
+        flip: None,
+        exec: |ctx, ops| {
+            if let Operand::Reg(r) = ops[0] {
+                let value = ctx.read(ops[0]) * 2;
+                ctx.write(r, value);
+            }
+            Control::Next
+        },
+    };
+
+    #[test]
+    fn test_register_opcode() {
+        init();
+
+        let mut isa = InstructionSet::boot();
+        isa.register(&DBL).unwrap();
+        assert!(isa.register(&opcodes::JMP).is_err());
+        assert_eq!(isa.mnemonics(), vec!["nop", "acc", "jmp", "dbl"]);
+
+        let mut vm = Vm::from_source_with_set(program(&["acc +3", "dbl a", "dbl a"]), ParseMode::Strict, isa).unwrap();
+        vm.record_history();
+        assert_eq!(vm.run(), Halt::Terminated { acc: 12 });
+        assert!(vm.step_back());
+        assert_eq!(vm.get_acc(), 6);
+
+        // Repair works the same with custom opcodes in the program
+        let mut isa = InstructionSet::boot();
+        isa.register(&DBL).unwrap();
+        let vm = Vm::from_source_with_set(program(&["acc +3", "dbl a", "jmp -1"]), ParseMode::Strict, isa).unwrap();
+        assert_eq!(vm.repair().map(|x| (x.pc, x.acc)), Ok((2, 6)));
+    }
+
+    #[test]
+    fn test_repair_conditional_jump() {
+        init();
+
+        // jnz/jz flip the same way jmp/nop do
+        let vm = Vm::from_source(program(&["set b 1", "acc +1", "jnz b -1", "acc +2"])).unwrap();
+        let fix = vm.repair().unwrap();
+        assert_eq!((fix.pc, fix.from, fix.to, fix.acc), (2, InstructionCode::JNZ, InstructionCode::JZ, 3));
+
+        // Flipping the nop at 1 lands on a jnz that could fall off the end,
+        //  but with b set it never does, so the jz at 2 is the real fix
+        let vm = Vm::from_source(program(&["set b 1", "nop +5", "jz b +2", "jmp -1", "set b 0", "acc +7", "jnz b +0"])).unwrap();
+        let fix = vm.repair().unwrap();
+        assert_eq!((fix.pc, fix.from, fix.to, fix.acc), (2, InstructionCode::JZ, InstructionCode::JNZ, 7));
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::VecDeque;
+use std::fmt;
+use super::channel::{Input, Output};
+
+/// Register names.  The first one is the accumulator the boot code uses.
+pub const REGISTERS: [&str; 8] = ["a", "b", "c", "d", "e", "f", "g", "h"];
+
+pub fn register_index(name: &str) -> Option<usize> {
+    REGISTERS.iter().position(|&r| r == name)
+}
+
+/// What an operand is allowed to be
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OperandKind {
+    /// A signed number, e.g. `+4`
+    Imm,
+    /// A register name, e.g. `b`
+    Reg,
+    /// Either of the above
+    Value,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operand {
+    Imm(i32),
+    Reg(usize),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Imm(x) => write!(f, "{:+}", x),
+            Operand::Reg(r) => write!(f, "{}", REGISTERS[*r]),
+        }
+    }
+}
+
+/// How an opcode can move the program counter, for static analysis.  The
+///  jump offset is always the opcode's last operand.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Flow {
+    /// Always falls through to the next instruction
+    Next,
+    /// Always jumps
+    Jump,
+    /// Either falls through or jumps, depending on the machine state
+    Branch,
+    /// Stops the program
+    Stop,
+}
+
+/// What the program counter should do after an instruction has run
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Control {
+    Next,
+    Jump(i32),
+    Halt,
+    /// Nothing to read; the instruction has to be tried again later
+    Block,
+}
+
+/// Machine state an instruction can see.  Every change goes through here so
+///  it can be recorded and undone.
+pub struct Context<'a> {
+    pub(super) registers: &'a mut [i32],
+    /// Values queued on the VM itself, read before anything from `source`
+    pub(super) input: &'a mut VecDeque<i32>,
+    pub(super) source: &'a mut Option<Box<dyn Input>>,
+    /// Everything the VM has sent, whether or not it also goes to `sink`
+    pub(super) output: &'a mut Vec<i32>,
+    pub(super) sink: &'a mut Option<Box<dyn Output>>,
+    pub(super) writes: Vec<(usize, i32)>,
+    pub(super) consumed: Option<i32>,
+    pub(super) produced: bool,
+}
+
+impl<'a> Context<'a> {
+    pub fn read(&self, operand: Operand) -> i32 {
+        match operand {
+            Operand::Imm(x) => x,
+            Operand::Reg(r) => self.registers[r],
+        }
+    }
+
+    pub fn write(&mut self, reg: usize, value: i32) {
+        self.writes.push((reg, self.registers[reg]));
+        self.registers[reg] = value;
+    }
+
+    /// Take the next input value, if there is one
+    pub fn receive(&mut self) -> Option<i32> {
+        let value = match self.input.pop_front() {
+            Some(value) => value,
+            None => self.source.as_mut()?.receive()?,
+        };
+        self.consumed = Some(value);
+        Some(value)
+    }
+
+    pub fn send(&mut self, value: i32) {
+        self.output.push(value);
+        if let Some(sink) = self.sink.as_mut() {
+            sink.send(value);
+        }
+        self.produced = true;
+    }
+}
+
+/// One entry in the opcode table
+pub struct Opcode {
+    pub mnemonic: &'static str,
+    pub operands: &'static [OperandKind],
+    pub flow: Flow,
+    /// The opcode this one turns into when repairing boot code
+    pub flip: Option<&'static Opcode>,
+    pub exec: fn(&mut Context, &[Operand]) -> Control,
+}
+
+fn reg(operand: Operand) -> usize {
+    match operand {
+        Operand::Reg(r) => r,
+        Operand::Imm(_) => panic!("Operand should have been parsed as a register"),
+    }
+}
+
+pub static NOP: Opcode = Opcode {
+    mnemonic: "nop",
+    operands: &[OperandKind::Imm],
+    flow: Flow::Next,
+    flip: Some(&JMP),
+    exec: |_, _| Control::Next,
+};
+
+pub static ACC: Opcode = Opcode {
+    mnemonic: "acc",
+    operands: &[OperandKind::Imm],
+    flow: Flow::Next,
+    flip: None,
+    exec: |ctx, ops| {
+        let value = ctx.registers[0].wrapping_add(ctx.read(ops[0]));
+        ctx.write(0, value);
+        Control::Next
+    },
+};
+
+pub static JMP: Opcode = Opcode {
+    mnemonic: "jmp",
+    operands: &[OperandKind::Imm],
+    flow: Flow::Jump,
+    flip: Some(&NOP),
+    exec: |ctx, ops| Control::Jump(ctx.read(ops[0])),
+};
+
+pub static SET: Opcode = Opcode {
+    mnemonic: "set",
+    operands: &[OperandKind::Reg, OperandKind::Value],
+    flow: Flow::Next,
+    flip: None,
+    exec: |ctx, ops| {
+        let value = ctx.read(ops[1]);
+        ctx.write(reg(ops[0]), value);
+        Control::Next
+    },
+};
+
+pub static ADD: Opcode = Opcode {
+    mnemonic: "add",
+    operands: &[OperandKind::Reg, OperandKind::Value],
+    flow: Flow::Next,
+    flip: None,
+    exec: |ctx, ops| {
+        let value = ctx.read(ops[0]).wrapping_add(ctx.read(ops[1]));
+        ctx.write(reg(ops[0]), value);
+        Control::Next
+    },
+};
+
+pub static MUL: Opcode = Opcode {
+    mnemonic: "mul",
+    operands: &[OperandKind::Reg, OperandKind::Value],
+    flow: Flow::Next,
+    flip: None,
+    exec: |ctx, ops| {
+        let value = ctx.read(ops[0]).wrapping_mul(ctx.read(ops[1]));
+        ctx.write(reg(ops[0]), value);
+        Control::Next
+    },
+};
+
+pub static JNZ: Opcode = Opcode {
+    mnemonic: "jnz",
+    operands: &[OperandKind::Value, OperandKind::Imm],
+    flow: Flow::Branch,
+    flip: Some(&JZ),
+    exec: |ctx, ops| if ctx.read(ops[0]) != 0 { Control::Jump(ctx.read(ops[1])) } else { Control::Next },
+};
+
+pub static JZ: Opcode = Opcode {
+    mnemonic: "jz",
+    operands: &[OperandKind::Value, OperandKind::Imm],
+    flow: Flow::Branch,
+    flip: Some(&JNZ),
+    exec: |ctx, ops| if ctx.read(ops[0]) == 0 { Control::Jump(ctx.read(ops[1])) } else { Control::Next },
+};
+
+pub static HLT: Opcode = Opcode {
+    mnemonic: "hlt",
+    operands: &[],
+    flow: Flow::Stop,
+    flip: None,
+    exec: |_, _| Control::Halt,
+};
+
+pub static IN: Opcode = Opcode {
+    mnemonic: "in",
+    operands: &[OperandKind::Reg],
+    flow: Flow::Next,
+    flip: None,
+    exec: |ctx, ops| match ctx.receive() {
+        Some(value) => {
+            ctx.write(reg(ops[0]), value);
+            Control::Next
+        },
+        None => Control::Block,
+    },
+};
+
+pub static OUT: Opcode = Opcode {
+    mnemonic: "out",
+    operands: &[OperandKind::Value],
+    flow: Flow::Next,
+    flip: None,
+    exec: |ctx, ops| {
+        let value = ctx.read(ops[0]);
+        ctx.send(value);
+        Control::Next
+    },
+};
+
+/// The opcodes a program can use.  New ones are added by defining an
+///  Opcode and registering it; nothing else needs to know about it.
+#[derive(Clone)]
+pub struct InstructionSet {
+    opcodes: Vec<&'static Opcode>,
+}
+
+impl InstructionSet {
+    /// Just nop, acc and jmp, as in the original boot code
+    #[allow(dead_code)]
+    pub fn boot() -> InstructionSet {
+        InstructionSet { opcodes: vec![&NOP, &ACC, &JMP] }
+    }
+
+    /// Everything defined above
+    pub fn standard() -> InstructionSet {
+        InstructionSet { opcodes: vec![&NOP, &ACC, &JMP, &SET, &ADD, &MUL, &JNZ, &JZ, &HLT, &IN, &OUT] }
+    }
+
+    /// Add an opcode.  Fails if its mnemonic is already taken.
+    #[allow(dead_code)]
+    pub fn register(&mut self, opcode: &'static Opcode) -> Result<(), String> {
+        if self.lookup(opcode.mnemonic).is_some() {
+            return Err(format!("Opcode `{}` is already registered", opcode.mnemonic));
+        }
+        self.opcodes.push(opcode);
+        Ok(())
+    }
+
+    pub fn lookup(&self, mnemonic: &str) -> Option<&'static Opcode> {
+        self.opcodes.iter().find(|op| op.mnemonic == mnemonic).copied()
+    }
+
+    #[allow(dead_code)]
+    pub fn mnemonics(&self) -> Vec<&'static str> {
+        self.opcodes.iter().map(|op| op.mnemonic).collect()
+    }
+}
+
+impl Default for InstructionSet {
+    fn default() -> InstructionSet {
+        InstructionSet::standard()
+    }
+}
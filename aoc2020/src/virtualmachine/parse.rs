@@ -0,0 +1,146 @@
+use std::fmt;
+use log::warn;
+use super::{Instruction, InstructionCode};
+use super::opcodes::{register_index, InstructionSet, Operand, OperandKind};
+
+/// What went wrong while reading a line of boot code
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParseErrorKind {
+    EmptyLine,
+    UnknownOpcode,
+    MissingOperand,
+    BadOperand,
+    TrailingInput,
+}
+
+/// A parse error, pointing at the offending token.  Lines and columns
+///  count from 1, like an editor would.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub token: String,
+    pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let what = match self.kind {
+            ParseErrorKind::EmptyLine => "empty line",
+            ParseErrorKind::UnknownOpcode => "unknown opcode",
+            ParseErrorKind::MissingOperand => "missing operand",
+            ParseErrorKind::BadOperand => "bad operand",
+            ParseErrorKind::TrailingInput => "unexpected trailing input",
+        };
+        if self.token.is_empty() {
+            write!(f, "line {}, column {}: {}", self.line, self.column, what)
+        } else {
+            write!(f, "line {}, column {}: {} `{}`", self.line, self.column, what, self.token)
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Strict parsing rejects anything it doesn't understand.  Lenient parsing
+///  logs a warning and turns the bad line into `nop +0`, which is how the
+///  VM has always treated it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParseMode {
+    Strict,
+    Lenient,
+}
+
+/// Whitespace separated tokens along with the column each one starts at
+fn tokenize(line: &str) -> Vec<(usize, &str)> {
+    let mut tokens = Vec::new();
+    let mut start: Option<usize> = None;
+
+    for (i, c) in line.char_indices() {
+        match (c.is_whitespace(), start) {
+            (true, Some(s)) => {
+                tokens.push((s, &line[s..i]));
+                start = None;
+            },
+            (false, None) => start = Some(i),
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        tokens.push((s, &line[s..]));
+    }
+
+    tokens.into_iter()
+        .map(|(s, tok)| (line[..s].chars().count() + 1, tok))
+        .collect()
+}
+
+pub fn parse_opcode(isa: &InstructionSet, token: &str) -> Option<InstructionCode> {
+    isa.lookup(token).map(InstructionCode)
+}
+
+fn parse_operand(kind: OperandKind, token: &str) -> Option<Operand> {
+    let reg = || register_index(token).map(Operand::Reg);
+    let imm = || token.parse::<i32>().ok().map(Operand::Imm);
+    match kind {
+        OperandKind::Imm => imm(),
+        OperandKind::Reg => reg(),
+        OperandKind::Value => reg().or_else(imm),
+    }
+}
+
+/// Parse one line of code, e.g. `jmp -4` or `jnz b -2`.  `line_no` is only
+///  used to fill in errors.
+pub fn parse_line(isa: &InstructionSet, line: &str, line_no: usize) -> Result<Instruction, ParseError> {
+    let error = |column: usize, token: &str, kind: ParseErrorKind| ParseError {
+        line: line_no,
+        column,
+        token: token.to_string(),
+        kind,
+    };
+    let tokens = tokenize(line);
+
+    let (op_col, op_tok) = match tokens.first() {
+        Some(&t) => t,
+        None => return Err(error(1, "", ParseErrorKind::EmptyLine)),
+    };
+    let operator = parse_opcode(isa, op_tok)
+        .ok_or_else(|| error(op_col, op_tok, ParseErrorKind::UnknownOpcode))?;
+
+    let kinds = operator.opcode().operands;
+    let mut operands = Vec::with_capacity(kinds.len());
+    for (i, &kind) in kinds.iter().enumerate() {
+        let (col, tok) = match tokens.get(i + 1) {
+            Some(&t) => t,
+            None => {
+                let (col, tok) = tokens[i];
+                return Err(error(col + tok.chars().count(), "", ParseErrorKind::MissingOperand));
+            }
+        };
+        operands.push(parse_operand(kind, tok).ok_or_else(|| error(col, tok, ParseErrorKind::BadOperand))?);
+    }
+
+    if let Some(&(col, tok)) = tokens.get(kinds.len() + 1) {
+        return Err(error(col, tok, ParseErrorKind::TrailingInput));
+    }
+
+    Ok(Instruction::new(operator, &operands))
+}
+
+/// Parse a whole program, one instruction per line
+pub fn parse_program(isa: &InstructionSet, lines: &[String], mode: ParseMode) -> Result<Vec<Instruction>, ParseError> {
+    let mut code = Vec::with_capacity(lines.len());
+
+    for (i, line) in lines.iter().enumerate() {
+        match (parse_line(isa, line, i + 1), mode) {
+            (Ok(inst), _) => code.push(inst),
+            (Err(e), ParseMode::Strict) => return Err(e),
+            (Err(e), ParseMode::Lenient) => {
+                warn!("{}; using nop +0", e);
+                code.push(Instruction::nop());
+            }
+        }
+    }
+
+    Ok(code)
+}
@@ -0,0 +1,234 @@
+use std::fmt;
+use super::{InstructionCode, Vm};
+
+/// Execution counts per program counter, collected while profiling is on.
+///  Stepping backwards doesn't take counts away; they record work done.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    counts: Vec<u64>,
+}
+
+impl Profile {
+    fn new(len: usize) -> Profile {
+        Profile { counts: vec![0; len] }
+    }
+
+    pub(super) fn hit(&mut self, pc: usize) {
+        if pc >= self.counts.len() {
+            self.counts.resize(pc + 1, 0);
+        }
+        self.counts[pc] += 1;
+    }
+
+    #[allow(dead_code)]
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    #[allow(dead_code)]
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+/// A loop found in the control flow graph, with how hard it was worked
+#[derive(Clone, Debug, PartialEq)]
+pub struct HotLoop {
+    /// First instruction of the loop header
+    pub header: usize,
+    /// Every instruction in the loop body, in order
+    pub body: Vec<usize>,
+    /// Times the header ran, i.e. times round the loop
+    pub iterations: u64,
+    /// Instructions executed inside the loop
+    pub executed: u64,
+}
+
+/// Everything the profiler knows, worked out from the per-pc counts
+pub struct ProfileReport {
+    instructions: Vec<String>,
+    disassembly: Vec<String>,
+    pub pc_counts: Vec<u64>,
+    /// Opcodes with how often they ran, most often first
+    pub opcode_counts: Vec<(InstructionCode, u64)>,
+    /// Times each basic block was entered, as numbered by the disassembler
+    pub block_counts: Vec<u64>,
+    pub block_of: Vec<usize>,
+    /// The loop that executed the most instructions, if any loop ran
+    pub hot_loop: Option<HotLoop>,
+}
+
+impl ProfileReport {
+    fn in_hot_loop(&self, pc: usize) -> bool {
+        self.hot_loop.as_ref().is_some_and(|l| l.body.contains(&pc))
+    }
+
+    /// One row per instruction: pc,instruction,count,block,in_loop
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("pc,instruction,count,block,in_loop\n");
+        for (pc, count) in self.pc_counts.iter().enumerate() {
+            out.push_str(&format!("{},{},{},{},{}\n", pc, self.instructions[pc], count, self.block_of[pc], self.in_hot_loop(pc)));
+        }
+        out
+    }
+}
+
+/// The disassembly with a count in front of every instruction.  Lines in
+///  the hottest loop are marked with `*`.
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total: u64 = self.pc_counts.iter().sum();
+        writeln!(f, "; {} instructions executed", total)?;
+        let opcodes: Vec<String> = self.opcode_counts.iter()
+            .map(|(op, count)| format!("{} {}", op, count))
+            .collect();
+        writeln!(f, "; by opcode: {}", opcodes.join(", "))?;
+        match &self.hot_loop {
+            Some(l) => writeln!(f, "; hottest loop: pc {}..={}, {} iterations, {} instructions executed",
+                l.body[0], l.body[l.body.len() - 1], l.iterations, l.executed)?,
+            None => writeln!(f, "; no loops ran")?,
+        }
+
+        let mut pc = 0;
+        let mut block = 0;
+        for line in &self.disassembly {
+            if line.starts_with(';') {
+                writeln!(f, "{:>8}   {}, entered {} times", "", line, self.block_counts[block])?;
+                block += 1;
+            } else {
+                let marker = if self.in_hot_loop(pc) { '*' } else { ' ' };
+                writeln!(f, "{:>8} {} {}", self.pc_counts[pc], marker, line)?;
+                pc += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Vm {
+    /// Start counting executions.  Counts already collected are kept.
+    #[allow(dead_code)]
+    pub fn enable_profiling(&mut self) {
+        if self.profile.is_none() {
+            self.profile = Some(Profile::new(self.code.len()));
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn disable_profiling(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    #[allow(dead_code)]
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Summary of the counts so far, or None if profiling is off
+    #[allow(dead_code)]
+    pub fn profile_report(&self) -> Option<ProfileReport> {
+        let profile = self.profile.as_ref()?;
+        let mut pc_counts = profile.counts.clone();
+        pc_counts.resize(self.code.len(), 0);
+        let cfg = self.control_flow_graph();
+
+        let mut opcode_counts: Vec<(InstructionCode, u64)> = Vec::new();
+        for (inst, &count) in self.code.iter().zip(pc_counts.iter()) {
+            match opcode_counts.iter_mut().find(|(op, _)| *op == inst.operator) {
+                Some(entry) => entry.1 += count,
+                None => opcode_counts.push((inst.operator, count)),
+            }
+        }
+        opcode_counts.retain(|&(_, count)| count > 0);
+        opcode_counts.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+
+        let block_counts: Vec<u64> = cfg.blocks.iter().map(|b| pc_counts[b.start]).collect();
+
+        let hot_loop = cfg.back_edges.iter()
+            .map(|&(from, header)| {
+                let body: Vec<usize> = cfg.natural_loop(from, header).into_iter()
+                    .flat_map(|b| cfg.blocks[b].start..=cfg.blocks[b].end)
+                    .collect();
+                let executed = body.iter().map(|&pc| pc_counts[pc]).sum();
+                HotLoop { header: cfg.blocks[header].start, body, iterations: block_counts[header], executed }
+            })
+            .filter(|l| l.executed > 0)
+            .max_by_key(|l| l.executed);
+
+        Some(ProfileReport {
+            instructions: self.code.iter().map(|x| x.to_string()).collect(),
+            disassembly: self.disassemble().lines().map(|x| x.to_string()).collect(),
+            pc_counts,
+            opcode_counts,
+            block_counts,
+            block_of: cfg.block_of,
+            hot_loop,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> Vm {
+        let input = vec![
+            "set c 3",
+            "acc +1",
+            "add c -1",
+            "jnz c -2",
+            "acc +10",
+            "jmp +2",
+            "acc -99",
+            "hlt",
+        ];
+        Vm::from_source(input.into_iter().map(|x| x.to_string()).collect()).unwrap()
+    }
+
+    #[test]
+    fn test_profile_counts() {
+        let mut vm = example();
+        assert!(vm.profile_report().is_none());
+
+        vm.enable_profiling();
+        vm.run_until_halt(100);
+        let profile = vm.profile().unwrap();
+        assert_eq!(profile.counts(), &[1, 3, 3, 3, 1, 1, 0, 1]);
+        assert_eq!(profile.total(), 13);
+
+        let report = vm.profile_report().unwrap();
+        assert_eq!(report.opcode_counts[0], (InstructionCode::ACC, 4));
+        assert_eq!(report.opcode_counts.iter().find(|x| x.0 == InstructionCode::HLT), Some(&(InstructionCode::HLT, 1)));
+        assert_eq!(report.block_counts, vec![1, 3, 1, 0, 1]);
+        assert_eq!(report.hot_loop, Some(HotLoop { header: 1, body: vec![1, 2, 3], iterations: 3, executed: 9 }));
+
+        // Counts survive stepping back
+        vm.step_back();
+        assert_eq!(vm.profile().unwrap().total(), 13);
+        assert_eq!(vm.disable_profiling().unwrap().total(), 13);
+        assert!(vm.profile().is_none());
+    }
+
+    #[test]
+    fn test_profile_report_output() {
+        let mut vm = example();
+        vm.enable_profiling();
+        vm.run_until_halt(100);
+        let report = vm.profile_report().unwrap();
+
+        let text = report.to_string();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "; 13 instructions executed");
+        assert_eq!(lines[1], "; by opcode: acc 4, add 3, jnz 3, set 1, jmp 1, hlt 1");
+        assert_eq!(lines[2], "; hottest loop: pc 1..=3, 3 iterations, 9 instructions executed");
+        assert_eq!(lines[5], "           ; block 1: 1..=3, entered 3 times");
+        assert_eq!(lines[6], "       3 *     1: acc +1");
+        assert!(lines.contains(&"       0       6: acc -99"));
+
+        let csv = report.to_csv();
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows[0], "pc,instruction,count,block,in_loop");
+        assert_eq!(rows[4], "3,jnz c -2,3,1,true");
+        assert_eq!(rows[8], "7,hlt,1,4,false");
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::VecDeque;
+use std::fmt;
+use super::{Halt, Instruction, InstructionCode, Vm};
+
+/// A single opcode flip that makes a program terminate
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Repair {
+    /// Index of the instruction that was changed
+    pub pc: usize,
+    pub from: InstructionCode,
+    pub to: InstructionCode,
+    /// Accumulator once the repaired program terminates
+    pub acc: i32,
+}
+
+/// Why repair has no flip to offer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RepairError {
+    /// The program terminates as it is
+    AlreadyTerminates,
+    /// It doesn't, and no single flip makes it
+    NoFix,
+}
+
+impl fmt::Display for RepairError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RepairError::AlreadyTerminates => write!(f, "the program already terminates"),
+            RepairError::NoFix => write!(f, "no single flip makes the program terminate"),
+        }
+    }
+}
+
+impl Vm {
+    /// Every instruction from which the unmodified program can run off the
+    ///  end cleanly.  Found by walking the control flow graph backwards from
+    ///  the end, so this is linear in the length of the program.  For code
+    ///  without conditional jumps "can" is the same as "will".
+    pub fn terminating_instructions(&self) -> Vec<bool> {
+        let n = self.code.len();
+        // Node n stands for "just past the end"
+        let mut predecessors: Vec<Vec<usize>> = vec![Vec::new(); n + 1];
+        for (i, inst) in self.code.iter().enumerate() {
+            for next in inst.successors(i as i32, n) {
+                if next >= 0 && next as usize <= n {
+                    predecessors[next as usize].push(i);
+                }
+            }
+        }
+
+        let mut terminating = vec![false; n + 1];
+        let mut queue = VecDeque::new();
+        terminating[n] = true;
+        queue.push_back(n);
+        while let Some(i) = queue.pop_front() {
+            for &p in &predecessors[i] {
+                if !terminating[p] {
+                    terminating[p] = true;
+                    queue.push_back(p);
+                }
+            }
+        }
+
+        terminating.truncate(n);
+        terminating
+    }
+
+    /// Find the single flip (jmp/nop, or jnz/jz) that makes the program
+    ///  terminate.  Only instructions the program actually reaches can
+    ///  matter, and flipping one of them can only help when it sends the
+    ///  program somewhere that can terminate.  For plain boot code that
+    ///  means running it at most twice.
+    pub fn repair(&self) -> Result<Repair, RepairError> {
+        let n = self.code.len();
+        let terminating = self.terminating_instructions();
+        let lands_safely = |pc: i32| pc == n as i32 || (pc >= 0 && pc < n as i32 && terminating[pc as usize]);
+        let fork = |code: Vec<Instruction>| {
+            let mut vm = Vm::from_code(code);
+            vm.isa = self.isa.clone();
+            vm
+        };
+
+        let mut trial = fork(self.code.clone());
+        trial.record_history();
+        if let Halt::Terminated { .. } = trial.run() {
+            return Err(RepairError::AlreadyTerminates);
+        }
+
+        // In the order the program first reached each instruction.  Without
+        //  conditional jumps the first candidate always works; with them,
+        //  "can terminate" doesn't mean it will, so keep going until one
+        //  actually does.
+        for pc in trial.get_history() {
+            let inst = self.code[pc as usize];
+            let to = match inst.operator.flipped() {
+                Some(to) => to,
+                None => continue,
+            };
+            let patched = inst.with_operator(to);
+            if !patched.successors(pc, n).into_iter().any(lands_safely) {
+                continue;
+            }
+
+            let mut vm = fork(self.code.clone());
+            vm.code[pc as usize] = patched;
+            if let Halt::Terminated { acc } = vm.run() {
+                return Ok(Repair { pc: pc as usize, from: inst.operator, to, acc });
+            }
+        }
+        Err(RepairError::NoFix)
+    }
+}
@@ -0,0 +1,276 @@
+use log::trace;
+use super::{Channel, Halt, Vm};
+
+/// How long to keep the machines going
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Schedule {
+    /// Give every machine this many turns
+    RoundRobin(usize),
+    /// Keep taking turns until no machine can do anything more
+    UntilBlocked,
+}
+
+/// Where a network of machines ended up
+#[derive(Clone, Debug, PartialEq)]
+pub enum Status {
+    /// Every machine stopped for good; holds why, one per machine
+    Halted(Vec<Halt>),
+    /// Nobody can move, and these machines are still waiting for input
+    Deadlock(Vec<usize>),
+    /// Used up the turns asked for with work still left to do
+    RoundLimit,
+}
+
+/// Runs several VMs turn by turn, each for up to `quantum` instructions
+///  at a time.  Loop detection is off, since machines talking to each
+///  other usually go round the same loop many times.
+pub struct Scheduler {
+    vms: Vec<Vm>,
+    /// Why each machine stopped for good, if it has
+    halted: Vec<Option<Halt>>,
+    quantum: usize,
+}
+
+#[allow(dead_code)]
+impl Scheduler {
+    pub fn new(quantum: usize) -> Scheduler {
+        Scheduler {
+            vms: Vec::new(),
+            halted: Vec::new(),
+            quantum: quantum.max(1),
+        }
+    }
+
+    /// Add a machine, returning its index
+    pub fn add(&mut self, vm: Vm) -> usize {
+        self.vms.push(vm);
+        self.halted.push(None);
+        self.vms.len() - 1
+    }
+
+    /// Send what machine `from` writes to machine `to`.  A machine has one
+    ///  output and one input, so this replaces any earlier connection.
+    pub fn connect(&mut self, from: usize, to: usize) -> Channel {
+        let channel = Channel::new();
+        self.vms[from].set_output(channel.clone());
+        self.vms[to].set_input(channel.clone());
+        channel
+    }
+
+    pub fn vm(&self, i: usize) -> &Vm {
+        &self.vms[i]
+    }
+
+    pub fn vm_mut(&mut self, i: usize) -> &mut Vm {
+        &mut self.vms[i]
+    }
+
+    pub fn len(&self) -> usize {
+        self.vms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vms.is_empty()
+    }
+
+    /// Run machine i for one turn.  Returns how many instructions it ran.
+    fn turn(&mut self, i: usize) -> usize {
+        if self.halted[i].is_some() {
+            return 0;
+        }
+        for ran in 0..self.quantum {
+            match self.vms[i].execute_once() {
+                None => {},
+                Some(Halt::Blocked { .. }) => return ran,
+                Some(halt) => {
+                    trace!("Machine {} stopped: {:?}", i, halt);
+                    self.halted[i] = Some(halt);
+                    return ran;
+                }
+            }
+        }
+        self.quantum
+    }
+
+    /// One turn for every machine, in order.  Returns whether any of them
+    ///  got anything done.
+    pub fn round(&mut self) -> bool {
+        let mut progress = false;
+        for i in 0..self.vms.len() {
+            progress |= self.turn(i) > 0;
+        }
+        progress
+    }
+
+    fn status(&self) -> Option<Status> {
+        if self.halted.iter().all(|h| h.is_some()) {
+            return Some(Status::Halted(self.halted.iter().map(|h| h.unwrap()).collect()));
+        }
+        None
+    }
+
+    fn waiting(&self) -> Vec<usize> {
+        (0..self.vms.len()).filter(|&i| self.halted[i].is_none()).collect()
+    }
+
+    pub fn run(&mut self, schedule: Schedule) -> Status {
+        let mut rounds = 0;
+        loop {
+            if let Some(status) = self.status() {
+                return status;
+            }
+            if let Schedule::RoundRobin(max) = schedule {
+                if rounds == max {
+                    return Status::RoundLimit;
+                }
+            }
+            rounds += 1;
+
+            if !self.round() {
+                // A round where nobody moved means everyone left is blocked
+                if let Some(status) = self.status() {
+                    return status;
+                }
+                return Status::Deadlock(self.waiting());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Output;
+    use super::*;
+
+    fn program(lines: &[&str]) -> Vm {
+        Vm::from_source(lines.iter().map(|x| x.to_string()).collect()).unwrap()
+    }
+
+    /// Reads a number, adds one and passes it on, forever
+    fn incrementer() -> Vm {
+        program(&["in a", "add a 1", "out a", "jmp -3"])
+    }
+
+    #[test]
+    fn test_pipeline_halts() {
+        let mut sched = Scheduler::new(10);
+        let source = sched.add(program(&["out 1", "out 2", "out 3", "hlt"]));
+        let square = sched.add(program(&[
+            "set c 3",
+            "in a",
+            "set b a",
+            "mul b a",
+            "out b",
+            "add c -1",
+            "jnz c -5",
+        ]));
+        sched.connect(source, square);
+
+        assert_eq!(sched.run(Schedule::UntilBlocked),
+            Status::Halted(vec![Halt::Terminated { acc: 0 }, Halt::Terminated { acc: 3 }]));
+        assert_eq!(sched.vm(square).get_output(), &[1, 4, 9]);
+    }
+
+    #[test]
+    fn test_ring_deadlocks() {
+        // A token goes round a ring of three machines, two of which add
+        //  one to it.  The first stops after seeing it come back twice.
+        let mut sched = Scheduler::new(1);
+        let a = sched.add(program(&[
+            "out 0",
+            "in a",
+            "add b 1",
+            "set c b",
+            "add c -2",
+            "jz c +3",
+            "out a",
+            "jmp -6",
+            "hlt",
+        ]));
+        let b = sched.add(incrementer());
+        let c = sched.add(incrementer());
+        sched.connect(a, b);
+        sched.connect(b, c);
+        let back = sched.connect(c, a);
+
+        assert_eq!(sched.run(Schedule::UntilBlocked), Status::Deadlock(vec![b, c]));
+        assert_eq!(sched.vm(b).get_output(), &[1, 3]);
+        assert_eq!(sched.vm(c).get_output(), &[2, 4]);
+        assert_eq!(sched.vm(a).get_acc(), 4);
+        assert!(back.is_empty());
+    }
+
+    #[test]
+    fn test_round_robin_limit() {
+        let mut sched = Scheduler::new(2);
+        let counter = sched.add(program(&["add a 1", "jmp -1"]));
+
+        assert_eq!(sched.run(Schedule::RoundRobin(5)), Status::RoundLimit);
+        assert_eq!(sched.vm(counter).get_acc(), 5);
+        assert_eq!(sched.vm(counter).steps(), 10);
+
+        // With input queued directly nothing needs to be connected
+        let mut sched = Scheduler::new(100);
+        let echo = sched.add(incrementer());
+        sched.vm_mut(echo).push_input(41);
+        assert_eq!(sched.run(Schedule::UntilBlocked), Status::Deadlock(vec![echo]));
+        assert_eq!(sched.vm(echo).get_output(), &[42]);
+    }
+
+    #[test]
+    fn test_channel_step_back() {
+        let mut producer = program(&["out 7", "out 8"]);
+        producer.record_history();
+        let channel = Channel::new();
+        producer.set_output(channel.clone());
+        producer.run();
+        assert_eq!(channel.contents(), vec![7, 8]);
+
+        assert!(producer.step_back());
+        assert_eq!(channel.contents(), vec![7]);
+
+        let mut consumer = program(&["in a", "in b"]);
+        consumer.record_history();
+        consumer.set_input(channel.clone());
+        consumer.push_input(6);
+        assert_eq!(consumer.run(), Halt::Terminated { acc: 6 });
+        assert!(channel.is_empty());
+
+        // Undone reads go back on the consumer's own queue
+        consumer.reset();
+        assert_eq!(consumer.run(), Halt::Terminated { acc: 6 });
+        assert_eq!(consumer.get_register("b"), Some(7));
+    }
+
+    #[test]
+    fn test_channel_shared_producers() {
+        let mut first = program(&["out 1"]);
+        let mut second = program(&["out 2"]);
+        let channel = Channel::new();
+        first.set_output(channel.clone());
+        second.set_output(channel.clone());
+        first.record_history();
+        second.record_history();
+        first.run();
+        second.run();
+
+        // 2 is in the way, so 1 stays; 2 can still be taken back
+        assert!(first.step_back());
+        assert_eq!(channel.contents(), vec![1, 2]);
+        assert!(second.step_back());
+        assert_eq!(channel.contents(), vec![1]);
+
+        // An equal value from someone else isn't ours to take either
+        let mut third = program(&["out 1"]);
+        third.set_output(channel.clone());
+        third.record_history();
+        third.run();
+        channel.clone().send(1);
+        channel.pop();
+        channel.pop();
+        assert_eq!(channel.contents(), vec![1]);
+        assert!(third.step_back());
+        assert_eq!(channel.contents(), vec![1]);
+    }
+}
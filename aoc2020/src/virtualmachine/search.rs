@@ -0,0 +1,331 @@
+use std::fmt;
+use super::opcodes::{Flow, Operand};
+use super::{Halt, Instruction, InstructionCode, Vm};
+
+/// Kinds of change the repair search may make to an instruction
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[allow(dead_code)]
+pub enum Mutation {
+    /// Swap the opcode for its flip (jmp/nop, jnz/jz)
+    Flip,
+    /// Move the last number operand by up to this much either way
+    ChangeOperand(i32),
+    /// Take the instruction out, closing the gap
+    Delete,
+}
+
+/// When a patched program counts as repaired
+#[derive(Clone, Copy, Debug)]
+#[allow(dead_code)]
+pub enum Goal {
+    Terminates,
+    TerminatesWithAcc(i32),
+    /// Terminates, and the final accumulator passes the test
+    AccMatches(fn(i32) -> bool),
+}
+
+impl Goal {
+    fn reached(&self, halt: &Halt) -> bool {
+        match (self, halt) {
+            (Goal::Terminates, Halt::Terminated { .. }) => true,
+            (Goal::TerminatesWithAcc(x), Halt::Terminated { acc }) => x == acc,
+            (Goal::AccMatches(test), Halt::Terminated { acc }) => test(*acc),
+            _ => false,
+        }
+    }
+}
+
+/// One change to the program.  pc always refers to the unpatched program.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Edit {
+    Flip { pc: usize, from: InstructionCode, to: InstructionCode },
+    Operand { pc: usize, from: i32, to: i32 },
+    Delete { pc: usize },
+}
+
+impl Edit {
+    pub fn pc(&self) -> usize {
+        match *self {
+            Edit::Flip { pc, .. } | Edit::Operand { pc, .. } | Edit::Delete { pc } => pc,
+        }
+    }
+}
+
+impl fmt::Display for Edit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Edit::Flip { pc, from, to } => write!(f, "{}: {} -> {}", pc, from, to),
+            Edit::Operand { pc, from, to } => write!(f, "{}: {:+} -> {:+}", pc, from, to),
+            Edit::Delete { pc } => write!(f, "{}: delete", pc),
+        }
+    }
+}
+
+/// What the repair search is allowed to do, and what it is looking for
+#[derive(Clone, Debug)]
+pub struct SearchOptions {
+    pub mutations: Vec<Mutation>,
+    pub max_edits: usize,
+    pub goal: Goal,
+    /// Run each candidate for at most this many steps instead of stopping
+    ///  at the first repeated instruction.  Needed for programs that loop
+    ///  on purpose, e.g. with jnz.
+    pub max_steps: Option<usize>,
+}
+
+impl Default for SearchOptions {
+    /// The day 8 puzzle: one flip, terminate
+    fn default() -> SearchOptions {
+        SearchOptions {
+            mutations: vec![Mutation::Flip],
+            max_edits: 1,
+            goal: Goal::Terminates,
+            max_steps: None,
+        }
+    }
+}
+
+/// Index of the operand ChangeOperand moves
+fn last_number(inst: &Instruction) -> Option<usize> {
+    inst.operands().iter().rposition(|op| matches!(op, Operand::Imm(_)))
+}
+
+impl Vm {
+    /// Every single-instruction change the options allow, by pc
+    fn candidate_edits(&self, mutations: &[Mutation]) -> Vec<Edit> {
+        let mut edits = Vec::new();
+        for (pc, inst) in self.code.iter().enumerate() {
+            for mutation in mutations {
+                match *mutation {
+                    Mutation::Flip => {
+                        if let Some(to) = inst.operator.flipped() {
+                            edits.push(Edit::Flip { pc, from: inst.operator, to });
+                        }
+                    },
+                    Mutation::ChangeOperand(range) => {
+                        if let Some(Operand::Imm(from)) = last_number(inst).map(|i| inst.operands[i]) {
+                            for delta in (-range..=range).filter(|&d| d != 0) {
+                                edits.push(Edit::Operand { pc, from, to: from.wrapping_add(delta) });
+                            }
+                        }
+                    },
+                    Mutation::Delete => edits.push(Edit::Delete { pc }),
+                }
+            }
+        }
+        edits
+    }
+
+    fn patched(&self, edits: &[Edit]) -> Vm {
+        let mut code = self.code.clone();
+        let mut deleted = Vec::new();
+        for edit in edits {
+            match *edit {
+                Edit::Flip { pc, to, .. } => code[pc] = code[pc].with_operator(to),
+                Edit::Operand { pc, to, .. } => {
+                    let i = last_number(&code[pc]).unwrap();
+                    code[pc].operands[i] = Operand::Imm(to);
+                },
+                Edit::Delete { pc } => deleted.push(pc),
+            }
+        }
+        // Jumps that cross a deleted instruction have to be shortened to
+        //  still land where they did, and ones that landed on it go to
+        //  whatever follows it.  Targets are the ones the control flow graph
+        //  uses, so a jump by a register is left alone.
+        deleted.sort_unstable();
+        let moved = |pc: i32| pc - deleted.iter().take_while(|&&d| (d as i32) < pc).count() as i32;
+        let len = code.len();
+        for (pc, inst) in code.iter_mut().enumerate() {
+            let last = match inst.operands().last() {
+                Some(Operand::Imm(_)) if matches!(inst.operator.opcode().flow, Flow::Jump | Flow::Branch) => inst.operands().len() - 1,
+                _ => continue,
+            };
+            let target = *inst.successors(pc as i32, len).last().unwrap();
+            inst.operands[last] = Operand::Imm(moved(target).saturating_sub(moved(pc as i32)));
+        }
+        // Back to front so the other pcs stay put
+        for &pc in deleted.iter().rev() {
+            code.remove(pc);
+        }
+        let mut vm = Vm::from_code(code);
+        vm.isa = self.isa.clone();
+        vm
+    }
+
+    /// Every smallest set of edits, up to options.max_edits of them and at
+    ///  most one per instruction, that makes the program reach the goal.
+    ///  Sets are listed with their edits in pc order.  Returns a single
+    ///  empty set if the program already reaches the goal, and nothing if
+    ///  no set within the limit does.
+    #[allow(dead_code)]
+    pub fn repair_search(&self, options: &SearchOptions) -> Vec<Vec<Edit>> {
+        let run = |vm: &mut Vm| match options.max_steps {
+            Some(max) => vm.run_until_halt(max),
+            None => vm.run(),
+        };
+
+        // Changing only instructions the program never gets to leaves the
+        //  run as it was, so such sets aren't worth running
+        let mut trial = self.patched(&[]);
+        if options.goal.reached(&run(&mut trial)) {
+            return vec![Vec::new()];
+        }
+        let reached: Vec<bool> = (0..self.code.len()).map(|pc| trial.has_visited(pc as i32)).collect();
+
+        let candidates = self.candidate_edits(&options.mutations);
+        for size in 1..=options.max_edits {
+            let mut found = Vec::new();
+            let mut chosen = Vec::with_capacity(size);
+            self.search_sets(&candidates, 0, size, &mut chosen, &mut |edits| {
+                let matters = edits.iter().any(|e| matches!(e, Edit::Delete { .. }) || reached[e.pc()]);
+                if matters && options.goal.reached(&run(&mut self.patched(edits))) {
+                    found.push(edits.to_vec());
+                }
+            });
+            if !found.is_empty() {
+                return found;
+            }
+        }
+        Vec::new()
+    }
+
+    /// Call visit with every way of picking `size` more edits from
+    ///  candidates[from..], at different pcs
+    fn search_sets(&self, candidates: &[Edit], from: usize, size: usize, chosen: &mut Vec<Edit>, visit: &mut dyn FnMut(&[Edit])) {
+        if size == 0 {
+            visit(chosen);
+            return;
+        }
+        for i in from..candidates.len() {
+            let edit = candidates[i];
+            if chosen.last().is_some_and(|last| last.pc() == edit.pc()) {
+                continue;
+            }
+            chosen.push(edit);
+            self.search_sets(candidates, i + 1, size - 1, chosen, visit);
+            chosen.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::day8_example;
+    use super::super::opcodes::{Control, Opcode, OperandKind};
+    use super::super::{InstructionSet, ParseMode};
+
+    fn program(lines: &[&str]) -> Vm {
+        Vm::from_source(lines.iter().map(|x| x.to_string()).collect()).unwrap()
+    }
+
+    #[test]
+    fn test_search_single_flip() {
+        let vm = day8_example();
+        let found = vm.repair_search(&SearchOptions::default());
+        assert_eq!(found, vec![vec![Edit::Flip { pc: 7, from: InstructionCode::JMP, to: InstructionCode::NOP }]]);
+        assert_eq!(found[0][0].to_string(), "7: jmp -> nop");
+        // Agrees with the one-flip repair
+        assert_eq!(vm.repair().unwrap().pc, 7);
+
+        // Nothing to do
+        assert_eq!(program(&["acc +1"]).repair_search(&SearchOptions::default()), vec![Vec::<Edit>::new()]);
+    }
+
+    #[test]
+    fn test_search_goal_and_edit_count() {
+        let vm = day8_example();
+        // Only the flip at 7 terminates, with acc 8, so asking for 7 needs
+        //  more than one edit
+        let options = SearchOptions { goal: Goal::TerminatesWithAcc(7), ..SearchOptions::default() };
+        assert!(vm.repair_search(&options).is_empty());
+
+        let options = SearchOptions { mutations: vec![Mutation::Flip, Mutation::Delete], max_edits: 2, goal: Goal::TerminatesWithAcc(7), max_steps: None };
+        let found = vm.repair_search(&options);
+        assert!(!found.is_empty());
+        for edits in &found {
+            assert_eq!(edits.len(), 2);
+            assert_eq!(vm.patched(edits).run(), Halt::Terminated { acc: 7 });
+        }
+        // Flip 7 and drop the final acc +1 from the loop body
+        assert!(found.contains(&vec![Edit::Delete { pc: 6 }, Edit::Flip { pc: 7, from: InstructionCode::JMP, to: InstructionCode::NOP }]));
+
+        let options = SearchOptions { goal: Goal::AccMatches(|acc| acc > 5), ..SearchOptions::default() };
+        assert_eq!(vm.repair_search(&options).len(), 1);
+    }
+
+    #[test]
+    fn test_search_two_flips() {
+        // Both jumps have to go
+        let vm = program(&["acc +1", "jmp +0", "acc +2", "jmp -1", "acc +4"]);
+        assert!(vm.repair_search(&SearchOptions::default()).is_empty());
+        let found = vm.repair_search(&SearchOptions { max_edits: 2, ..SearchOptions::default() });
+        assert_eq!(found, vec![vec![
+            Edit::Flip { pc: 1, from: InstructionCode::JMP, to: InstructionCode::NOP },
+            Edit::Flip { pc: 3, from: InstructionCode::JMP, to: InstructionCode::NOP },
+        ]]);
+    }
+
+    #[test]
+    fn test_search_change_operand() {
+        let vm = program(&["acc +1", "jmp -1", "acc +2"]);
+        let options = SearchOptions { mutations: vec![Mutation::ChangeOperand(3)], ..SearchOptions::default() };
+        let found = vm.repair_search(&options);
+        assert_eq!(found, vec![
+            vec![Edit::Operand { pc: 1, from: -1, to: 1 }],
+            vec![Edit::Operand { pc: 1, from: -1, to: 2 }],
+        ]);
+        assert_eq!(found[0][0].to_string(), "1: -1 -> +1");
+
+        // A counting loop would look like an infinite loop without a step
+        //  limit
+        let vm = program(&["set b 3", "add b -1", "jnz b -1", "jmp -3"]);
+        let options = SearchOptions { mutations: vec![Mutation::Delete], max_steps: Some(100), ..SearchOptions::default() };
+        assert_eq!(vm.repair_search(&options), vec![vec![Edit::Delete { pc: 3 }]]);
+        assert!(vm.repair_search(&SearchOptions { max_steps: None, ..options }).is_empty());
+
+        // The jmp only runs once the loop is done, so it has to count as
+        //  reached
+        let options = SearchOptions { max_steps: Some(100), ..SearchOptions::default() };
+        let found = vm.repair_search(&options);
+        assert_eq!(found, vec![vec![Edit::Flip { pc: 3, from: InstructionCode::JMP, to: InstructionCode::NOP }]]);
+        assert_eq!(vm.patched(&found[0]).run_until_halt(100), Halt::Terminated { acc: 0 });
+    }
+
+    #[test]
+    fn test_search_delete_keeps_jumps() {
+        let vm = program(&["jmp +3", "acc +10", "acc +20", "acc +1", "jmp -3"]);
+        // Both jumps cross the deleted acc +20 and get one shorter
+        assert_eq!(vm.patched(&[Edit::Delete { pc: 2 }]).run(), Halt::InfiniteLoop { pc: 2, acc: 11 });
+        // The jmp +3 landed on the deleted instruction, so now lands on the
+        //  one after it
+        assert_eq!(vm.patched(&[Edit::Delete { pc: 3 }]).run(), Halt::InfiniteLoop { pc: 3, acc: 30 });
+        // Off the end is still off the end
+        let vm = program(&["jmp +3", "acc +1", "acc +2"]);
+        assert_eq!(vm.patched(&[Edit::Delete { pc: 1 }]).run(), Halt::Terminated { acc: 0 });
+    }
+
+    /// `jrn x r`: jump by register r if x isn't zero
+    static JRN: Opcode = Opcode {
+        mnemonic: "jrn",
+        operands: &[OperandKind::Imm, OperandKind::Reg],
+        flow: Flow::Branch,
+        flip: None,
+        exec: |ctx, ops| if ctx.read(ops[0]) != 0 { Control::Jump(ctx.read(ops[1])) } else { Control::Next },
+    };
+
+    #[test]
+    fn test_search_delete_register_jump() {
+        let mut isa = InstructionSet::standard();
+        isa.register(&JRN).unwrap();
+        let lines = ["set b 3", "jrn 3 b", "acc +1", "acc +2", "acc +4"].iter().map(|x| x.to_string()).collect();
+        let vm = Vm::from_source_with_set(lines, ParseMode::Strict, isa).unwrap();
+
+        // The 3 is a condition, not an offset, so a deletion after it
+        //  leaves it as it is, just as the control flow graph sees it
+        let patched = vm.patched(&[Edit::Delete { pc: 2 }]);
+        assert_eq!(patched.code[1], vm.code[1]);
+        assert_eq!(vm.code[1].successors(1, 5), vec![2]);
+    }
+}
@@ -0,0 +1,399 @@
+use std::collections::VecDeque;
+use super::opcodes::register_index;
+use super::{parse, Instruction, InstructionSet, Step, Vm, REGISTERS};
+
+/// First line of a saved VM.  Bump the number if the format changes.
+const HEADER: &str = "vm v2";
+
+/// Everything needed to put a VM back the way it was, except where its
+///  input comes from and its output goes, which can't be copied.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    code: Vec<Instruction>,
+    pc: i32,
+    registers: Vec<i32>,
+    input: VecDeque<i32>,
+    read: Vec<i32>,
+    output: Vec<i32>,
+    steps: usize,
+    visits: Vec<u32>,
+    history: Option<Vec<Step>>,
+}
+
+impl Snapshot {
+    /// Write out in a line based text format that later versions can still
+    ///  read:
+    ///
+    /// ```text
+    /// vm v2
+    /// pc 2
+    /// registers 1 0 0 0 0 0 0 0
+    /// input
+    /// read
+    /// output
+    /// steps 2
+    /// code 3
+    /// nop +0
+    /// acc +1
+    /// jmp -2
+    /// visits 1 1 0
+    /// history 2
+    /// step 0
+    /// step 1 a=0
+    /// ```
+    ///
+    /// `read` is the input taken since the last reset and `visits` how
+    ///  often each instruction has run.  History is `history off` unless it
+    ///  is being recorded.  A step lists the registers it wrote with their
+    ///  old values, then `in=<value>` if it read input and `out` if it
+    ///  wrote output.
+    pub fn serialize(&self) -> String {
+        let numbers = |values: &mut dyn Iterator<Item = &i32>| -> String {
+            values.map(|x| format!(" {}", x)).collect()
+        };
+
+        let mut out = String::new();
+        out.push_str(&format!("{}\n", HEADER));
+        out.push_str(&format!("pc {}\n", self.pc));
+        out.push_str(&format!("registers{}\n", numbers(&mut self.registers.iter())));
+        out.push_str(&format!("input{}\n", numbers(&mut self.input.iter())));
+        out.push_str(&format!("read{}\n", numbers(&mut self.read.iter())));
+        out.push_str(&format!("output{}\n", numbers(&mut self.output.iter())));
+        out.push_str(&format!("steps {}\n", self.steps));
+
+        out.push_str(&format!("code {}\n", self.code.len()));
+        for inst in &self.code {
+            out.push_str(&format!("{}\n", inst));
+        }
+        let visits: String = self.visits.iter().map(|x| format!(" {}", x)).collect();
+        out.push_str(&format!("visits{}\n", visits));
+
+        let history = match &self.history {
+            Some(history) => history,
+            None => {
+                out.push_str("history off\n");
+                return out;
+            },
+        };
+        out.push_str(&format!("history {}\n", history.len()));
+        for step in history {
+            out.push_str(&format!("step {}", step.pc));
+            for &(reg, old) in &step.writes {
+                out.push_str(&format!(" {}={}", REGISTERS[reg], old));
+            }
+            if let Some(value) = step.input {
+                out.push_str(&format!(" in={}", value));
+            }
+            if step.output {
+                out.push_str(" out");
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Read back what serialize wrote.  Errors say which line is wrong.
+    ///  The counts have to agree with the history, so that stepping back
+    ///  through it can't take them below zero.
+    pub fn deserialize(text: &str, isa: &InstructionSet) -> Result<Snapshot, String> {
+        let mut lines = text.lines().enumerate().map(|(i, line)| (i + 1, line));
+        let mut next = |what: &str| lines.next().ok_or(format!("unexpected end of input, expected {}", what));
+
+        let (n, line) = next("header")?;
+        if line.trim() != HEADER {
+            return Err(format!("line {}: expected `{}`", n, HEADER));
+        }
+
+        let (n, line) = next("pc")?;
+        let pc = number(n, field(n, line, "pc")?.trim())?;
+
+        let (n, line) = next("registers")?;
+        let registers = numbers(n, field(n, line, "registers")?)?;
+        if registers.len() != REGISTERS.len() {
+            return Err(format!("line {}: expected {} registers, found {}", n, REGISTERS.len(), registers.len()));
+        }
+
+        let (n, line) = next("input")?;
+        let input = numbers(n, field(n, line, "input")?)?.into_iter().collect();
+        let (n, line) = next("read")?;
+        let read = numbers(n, field(n, line, "read")?)?;
+        let (n, line) = next("output")?;
+        let output = numbers(n, field(n, line, "output")?)?;
+        let (n, line) = next("steps")?;
+        let steps = count(n, field(n, line, "steps")?)?;
+
+        let (n, line) = next("code")?;
+        let len = count(n, field(n, line, "code")?)?;
+        let mut code = Vec::with_capacity(len);
+        for _ in 0..len {
+            let (n, line) = next("an instruction")?;
+            code.push(parse::parse_line(isa, line, n).map_err(|e| e.to_string())?);
+        }
+        let (visits_line, line) = next("visits")?;
+        let visits = field(visits_line, line, "visits")?
+            .split_whitespace()
+            .map(|x| x.parse::<u32>().map_err(|_| format!("line {}: bad count `{}`", visits_line, x)))
+            .collect::<Result<Vec<u32>, String>>()?;
+        if visits.len() != code.len() {
+            return Err(format!("line {}: expected {} visit counts, found {}", visits_line, code.len(), visits.len()));
+        }
+        let total: u64 = visits.iter().map(|&x| x as u64).sum();
+        if total != steps as u64 {
+            return Err(format!("line {}: visit counts add up to {}, but {} steps have run", visits_line, total, steps));
+        }
+
+        let (n, line) = next("history")?;
+        let rest = field(n, line, "history")?;
+        let history = if rest.trim() == "off" {
+            None
+        } else {
+            let len = count(n, rest)?;
+            let mut history = Vec::with_capacity(len);
+            for _ in 0..len {
+                let (n, line) = next("a step")?;
+                let step = parse_step(n, field(n, line, "step")?)?;
+                if step.pc < 0 || step.pc as usize >= code.len() {
+                    return Err(format!("line {}: step at pc {} is outside the program", n, step.pc));
+                }
+                history.push(step);
+            }
+            Some(history)
+        };
+
+        if let Some((n, line)) = lines.find(|(_, line)| !line.trim().is_empty()) {
+            return Err(format!("line {}: unexpected `{}`", n, line.trim()));
+        }
+
+        // Recording may have started after the last reset, so the history
+        //  can be shorter than the counts but never longer
+        if let Some(history) = &history {
+            let mut recorded = vec![0u32; code.len()];
+            for step in history {
+                recorded[step.pc as usize] += 1;
+            }
+            if let Some(pc) = (0..code.len()).find(|&pc| recorded[pc] > visits[pc]) {
+                return Err(format!("line {}: pc {} has run {} times, but the history has {} steps there", visits_line, pc, visits[pc], recorded[pc]));
+            }
+            let inputs = history.iter().filter(|step| step.input.is_some()).count();
+            if inputs > read.len() {
+                return Err(format!("history has {} steps that read input, but only {} values were read", inputs, read.len()));
+            }
+        }
+
+        Ok(Snapshot { code, pc, registers, input, read, output, steps, visits, history })
+    }
+}
+
+/// The rest of a line that starts with `name`
+fn field<'a>(n: usize, line: &'a str, name: &str) -> Result<&'a str, String> {
+    let rest = line.strip_prefix(name).ok_or(format!("line {}: expected `{}`", n, name))?;
+    if !rest.is_empty() && !rest.starts_with(' ') {
+        return Err(format!("line {}: expected `{}`", n, name));
+    }
+    Ok(rest)
+}
+
+fn number(n: usize, s: &str) -> Result<i32, String> {
+    s.parse::<i32>().map_err(|_| format!("line {}: bad number `{}`", n, s))
+}
+
+fn numbers(n: usize, s: &str) -> Result<Vec<i32>, String> {
+    s.split_whitespace().map(|x| number(n, x)).collect()
+}
+
+fn count(n: usize, s: &str) -> Result<usize, String> {
+    s.trim().parse::<usize>().map_err(|_| format!("line {}: bad count `{}`", n, s.trim()))
+}
+
+fn parse_step(n: usize, s: &str) -> Result<Step, String> {
+    let mut words = s.split_whitespace();
+    let pc = number(n, words.next().ok_or(format!("line {}: step is missing its pc", n))?)?;
+    let mut step = Step { pc, writes: Vec::new(), input: None, output: false };
+
+    for word in words {
+        match word.split_once('=') {
+            None if word == "out" => step.output = true,
+            Some(("in", value)) => step.input = Some(number(n, value)?),
+            Some((reg, value)) => {
+                let reg = register_index(reg).ok_or(format!("line {}: unknown register `{}`", n, reg))?;
+                step.writes.push((reg, number(n, value)?));
+            },
+            None => return Err(format!("line {}: unexpected `{}`", n, word)),
+        }
+    }
+    Ok(step)
+}
+
+impl Vm {
+    /// Copy of the current state, to go back to or fork from later
+    #[allow(dead_code)]
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            code: self.code.clone(),
+            pc: self.pc,
+            registers: self.registers.clone(),
+            input: self.input.clone(),
+            read: self.read.clone(),
+            output: self.output.clone(),
+            steps: self.steps,
+            visits: self.visits.clone(),
+            history: self.history.clone(),
+        }
+    }
+
+    /// Go back to a snapshot.  Input source and output sink stay as they
+    ///  are.
+    #[allow(dead_code)]
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.code = snapshot.code.clone();
+        self.pc = snapshot.pc;
+        self.registers = snapshot.registers.clone();
+        self.input = snapshot.input.clone();
+        self.read = snapshot.read.clone();
+        self.output = snapshot.output.clone();
+        self.steps = snapshot.steps;
+        self.visits = snapshot.visits.clone();
+        self.history = snapshot.history.clone();
+    }
+
+    /// A new VM in the state of a snapshot
+    #[allow(dead_code)]
+    pub fn from_snapshot(snapshot: &Snapshot) -> Vm {
+        let mut vm = Vm::from_code(Vec::new());
+        vm.restore(snapshot);
+        vm
+    }
+
+    /// The whole state as text; see Snapshot::serialize
+    #[allow(dead_code)]
+    pub fn serialize(&self) -> String {
+        self.snapshot().serialize()
+    }
+
+    /// Load a VM saved with serialize, using the standard instruction set
+    #[allow(dead_code)]
+    pub fn deserialize(text: &str) -> Result<Vm, String> {
+        Vm::deserialize_with_set(text, InstructionSet::standard())
+    }
+
+    #[allow(dead_code)]
+    pub fn deserialize_with_set(text: &str, isa: InstructionSet) -> Result<Vm, String> {
+        let snapshot = Snapshot::deserialize(text, &isa)?;
+        let mut vm = Vm::from_snapshot(&snapshot);
+        vm.isa = isa;
+        Ok(vm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{day8_example, Halt, InstructionCode};
+
+    #[test]
+    fn test_snapshot_restore() {
+        let mut vm = day8_example();
+        vm.run_with_limit(3);
+        let checkpoint = vm.snapshot();
+
+        assert_eq!(vm.run(), Halt::InfiniteLoop { pc: 1, acc: 5 });
+        vm.restore(&checkpoint);
+        assert_eq!((vm.get_pc(), vm.get_acc(), vm.steps()), (6, 1, 3));
+        assert!(vm.has_visited(2) && !vm.has_visited(6));
+
+        // Fork from the checkpoint, trying each flip the rest of the run
+        //  reaches without starting again from pc 0
+        let mut fixed = None;
+        for pc in [6, 7, 3, 4] {
+            let mut fork = Vm::from_snapshot(&checkpoint);
+            let inst = fork.code[pc];
+            if let Some(to) = inst.operator.flipped() {
+                fork.code[pc] = inst.with_operator(to);
+                if let Halt::Terminated { acc } = fork.run() {
+                    fixed = Some((pc, to, acc));
+                    break;
+                }
+            }
+        }
+        assert_eq!(fixed, Some((7, InstructionCode::NOP, 8)));
+    }
+
+    #[test]
+    fn test_serialize_round_trip() {
+        let mut vm = Vm::from_source(vec!["in b".to_string(), "acc +2".to_string(), "out b".to_string(), "jmp -3".to_string()]).unwrap();
+        vm.push_input(5);
+        vm.push_input(6);
+        vm.record_history();
+        vm.run_with_limit(3);
+
+        let text = vm.serialize();
+        assert_eq!(text, "\
+vm v2
+pc 3
+registers 2 5 0 0 0 0 0 0
+input 6
+read 5
+output 5
+steps 3
+code 4
+in b
+acc +2
+out b
+jmp -3
+visits 1 1 1 0
+history 3
+step 0 b=0 in=5
+step 1 a=0
+step 2 out
+");
+
+        let mut copy = Vm::deserialize(&text).unwrap();
+        assert_eq!(copy.snapshot(), vm.snapshot());
+        assert_eq!(copy.run_until_halt(10), Halt::Blocked { pc: 0 });
+        assert_eq!(copy.get_output(), &[5, 6]);
+
+        // History comes back too, so it can still be undone
+        assert!(copy.rewind_to(0));
+        assert_eq!((copy.get_acc(), copy.get_register("b")), (0, Some(0)));
+
+        // Without a history the counts still come back, and reset still
+        //  knows what input to queue again
+        let mut vm = Vm::from_source(vec!["in b".to_string(), "acc +2".to_string(), "jmp -2".to_string()]).unwrap();
+        vm.push_input(5);
+        vm.run_with_limit(3);
+        let mut copy = Vm::deserialize(&vm.serialize()).unwrap();
+        assert!(copy.serialize().ends_with("visits 1 1 1\nhistory off\n"));
+        assert_eq!(copy.run(), Halt::InfiniteLoop { pc: 0, acc: 2 });
+        assert_eq!(copy.steps(), 3);
+        copy.reset();
+        assert_eq!(copy.run(), Halt::InfiniteLoop { pc: 0, acc: 2 });
+        assert_eq!(copy.get_register("b"), Some(5));
+    }
+
+    #[test]
+    fn test_deserialize_errors() {
+        let text = day8_example().serialize();
+
+        assert_eq!(Vm::deserialize("vm v3\n").err().unwrap(), "line 1: expected `vm v2`");
+        assert_eq!(Vm::deserialize(&text.replace("pc 0", "pc x")).err().unwrap(), "line 2: bad number `x`");
+        assert_eq!(Vm::deserialize(&text.replace("jmp -4", "jpm -4")).err().unwrap(), "line 16, column 1: unknown opcode `jpm`");
+        assert_eq!(Vm::deserialize(&text.replace("code 9", "code 10")).err().unwrap(), "line 18, column 1: unknown opcode `visits`");
+        assert_eq!(Vm::deserialize(&text.replace("visits 0", "visits")).err().unwrap(), "line 18: expected 9 visit counts, found 8");
+        assert_eq!(Vm::deserialize(&text.replace("history off", "history 1")).err().unwrap(), "unexpected end of input, expected a step");
+        assert_eq!(Vm::deserialize(&format!("{}step 0\n", text)).err().unwrap(), "line 20: unexpected `step 0`");
+        assert_eq!(Vm::deserialize(&text.replace("visits 0", "visits -1")).err().unwrap(), "line 18: bad count `-1`");
+        assert_eq!(Vm::deserialize(&text.replace("visits 0", "visits 1")).err().unwrap(), "line 18: visit counts add up to 1, but 0 steps have run");
+
+        // Counts that stepping back through the history would take below
+        //  zero
+        let mut vm = day8_example();
+        vm.record_history();
+        vm.run_with_limit(3);
+        let text = vm.serialize();
+        assert!(text.contains("steps 3\n") && text.contains("visits 1 1 1 0 0 0 0 0 0\n"));
+        let edited = text.replace("steps 3", "steps 2").replace("visits 1 1 1", "visits 1 0 1");
+        assert_eq!(Vm::deserialize(&edited).err().unwrap(), "line 18: pc 1 has run 0 times, but the history has 1 steps there");
+        let edited = text.replace("steps 3", "steps 4").replace("visits 1 1 1 0", "visits 1 1 1 1");
+        assert!(Vm::deserialize(&edited).is_ok());
+    }
+}